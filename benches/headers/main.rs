@@ -0,0 +1,73 @@
+// Header decoding with the generated perfect hash against the decoder it
+// replaced, taken verbatim from the baseline commit. Run with
+// `cargo bench --bench headers`.
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+// shadowapi is a binary crate, so the decoder and the types it needs are
+// compiled into the benchmark directly. Their tests are not run from here.
+#[allow(dead_code, unused_imports)]
+#[path = "../../src/http"]
+mod http {
+    pub mod decoders;
+    pub mod error;
+    pub mod field_hash;
+    pub mod partials;
+}
+
+mod legacy_trie;
+
+// Header names as they show up in responses: mostly known, some in another
+// case and some the decoder does not know.
+const NAMES: [&str; 12] = [
+    "Content-Type",
+    "Content-Length",
+    "Date",
+    "Server",
+    "cache-control",
+    "ETag",
+    "Access-Control-Allow-Origin",
+    "Strict-Transport-Security",
+    "X-Request-ID",
+    "vary",
+    "X-Custom-Header",
+    "Set-Cookie",
+];
+
+fn recognize(c: &mut Criterion) {
+    c.bench_function("recognize", |b| {
+        b.iter(|| {
+            for name in NAMES.iter() {
+                black_box(http::decoders::recognize(black_box(name.as_bytes())));
+            }
+        })
+    });
+}
+
+// Both decoders take the end of the line pointing at the \n. Note that the
+// baseline `len_match` rejects a line whose ':' comes before its end, so it
+// walks the trie but returns None for these lines without building a pair.
+// Its numbers are a lower bound on what it cost.
+fn decode_header(c: &mut Criterion) {
+    let lines: Vec<String> = NAMES.iter().map(|name| format!("{}: some value\r\n", name)).collect();
+    let mut group = c.benchmark_group("decode_header");
+    group.bench_function("baseline", |b| {
+        b.iter(|| {
+            for line in lines.iter() {
+                let _ = black_box(legacy_trie::decode_header(black_box(line.as_bytes()), 0, line.len() - 1));
+            }
+        })
+    });
+    group.bench_function("perfect_hash", |b| {
+        b.iter(|| {
+            for line in lines.iter() {
+                let _ = black_box(http::decoders::decode_header(black_box(line.as_bytes()), 0, line.len() - 1));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, recognize, decode_header);
+criterion_main!(benches);
@@ -0,0 +1,127 @@
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+// Only the hashing is needed here, not the comparison.
+#[allow(dead_code)]
+#[path = "src/http/field_hash.rs"]
+mod field_hash;
+
+// Generates the HttpHeader enum and a perfect hash table for field names out
+// of the table in src/http/fields.txt. The output is included by
+// src/http/partials.rs.
+fn main() {
+    // Relative to this file so the fuzz crate can use it as its build script
+    // too.
+    let dir = Path::new(file!()).parent().unwrap_or(Path::new(""));
+    let table = dir.join("src/http/fields.txt");
+    println!("cargo:rerun-if-changed={}", table.display());
+    println!("cargo:rerun-if-changed={}", dir.join("src/http/field_hash.rs").display());
+
+    let text = std::fs::read_to_string(&table).expect("field table should be readable");
+    let names: Vec<&str> = text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+
+    let mut variants: Vec<String> = Vec::with_capacity(names.len());
+    for name in names.iter() {
+        if !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-') {
+            panic!("field name {} should only have letters, digits and dashes", name);
+        }
+        let variant = variant(name);
+        if variants.contains(&variant) {
+            panic!("field name {} is in the table twice", name);
+        }
+        variants.push(variant);
+    }
+
+    let (bits, displacements, slots) = perfect_hash(&names);
+
+    let mut out = String::new();
+
+    out.push_str("#[allow(clippy::upper_case_acronyms)]\n");
+    out.push_str("#[derive(Debug, Clone, PartialEq)]\npub enum HttpHeader {\n");
+    for variant in variants.iter() {
+        let _ = writeln!(out, "    {},", variant);
+    }
+    out.push_str("}\n\n");
+
+    out.push_str("impl From<&HttpHeader> for &str {\n    fn from(value: &HttpHeader) -> Self {\n        match value {\n");
+    for (variant, name) in variants.iter().zip(names.iter()) {
+        let _ = writeln!(out, "            HttpHeader::{} => \"{}\",", variant, name);
+    }
+    out.push_str("        }\n    }\n}\n\n");
+
+    let _ = writeln!(out, "// Every known header, in the order of the table.\npub static HEADERS: [HttpHeader; {}] = [", names.len());
+    for variant in variants.iter() {
+        let _ = writeln!(out, "    HttpHeader::{},", variant);
+    }
+    out.push_str("];\n\n");
+
+    let _ = writeln!(out, "// The field names of HEADERS.\npub static FIELD_NAMES: [&str; {}] = {:?};\n", names.len(), names);
+
+    let _ = writeln!(out, "// The lookup table has 2^FIELD_BITS slots.\npub const FIELD_BITS: u32 = {};\n", bits);
+    let _ = writeln!(out, "pub static FIELD_DISPLACEMENTS: [u64; {}] = {:?};\n", displacements.len(), displacements);
+    let _ = writeln!(
+        out,
+        "// Index into HEADERS per slot, u16::MAX for an empty slot.\npub static FIELD_SLOTS: [u16; {}] = {:?};",
+        slots.len(),
+        slots
+    );
+
+    let dest = PathBuf::from(std::env::var("OUT_DIR").expect("cargo sets OUT_DIR")).join("fields.rs");
+    std::fs::write(dest, out).expect("generated fields should be writable");
+}
+
+// Hash and displace: names are split over buckets by their hash, then every
+// bucket, largest first, gets the first displacement that puts all of its
+// names in free slots. Returns the table size in bits, the displacement per
+// bucket and the index of the name per slot.
+fn perfect_hash(names: &[&str]) -> (u32, Vec<u64>, Vec<u16>) {
+    let hashes: Vec<u64> = names.iter().map(|n| field_hash::hash(n.as_bytes())).collect();
+
+    // Half full, which keeps the search for displacements short.
+    let bits = (names.len() * 2).next_power_of_two().trailing_zeros();
+    let buckets = names.len().div_ceil(4).max(1);
+
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); buckets];
+    for (i, &h) in hashes.iter().enumerate() {
+        members[field_hash::bucket(h, buckets)].push(i);
+    }
+    let mut order: Vec<usize> = (0..buckets).collect();
+    order.sort_by_key(|&b| std::cmp::Reverse(members[b].len()));
+
+    let mut displacements: Vec<u64> = vec![0; buckets];
+    let mut slots: Vec<u16> = vec![u16::MAX; 1 << bits];
+    for b in order {
+        if members[b].is_empty() {
+            continue;
+        }
+        let found = (0..1_000_000u64).find_map(|d| {
+            let taken: Vec<usize> = members[b].iter().map(|&i| field_hash::slot(hashes[i], d, bits)).collect();
+            let free = taken.iter().enumerate().all(|(k, &s)| slots[s] == u16::MAX && !taken[..k].contains(&s));
+            free.then_some((d, taken))
+        });
+        let (d, taken) = found.unwrap_or_else(|| panic!("no displacement found for bucket {}", b));
+        displacements[b] = d;
+        for (&i, s) in members[b].iter().zip(taken) {
+            slots[s] = i as u16;
+        }
+    }
+
+    return (bits, displacements, slots);
+}
+
+// Content-Length becomes ContentLength, X-UA-Compatible becomes XUACompatible.
+fn variant(name: &str) -> String {
+    name.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
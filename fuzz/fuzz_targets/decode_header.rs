@@ -0,0 +1,30 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// shadowapi is a binary crate, so the decoder and the types it needs are
+// compiled into the fuzz target directly.
+#[allow(dead_code)]
+#[path = "../../src/http"]
+mod http {
+    pub mod decoders;
+    pub mod error;
+    pub mod field_hash;
+    pub mod partials;
+}
+
+use http::decoders::decode_header;
+
+fuzz_target!(|data: &[u8]| {
+    // Every line of the input, the way the response decoder calls it.
+    let mut start = 0;
+    while let Some(lf) = data[start..].iter().position(|&b| b == b'\n') {
+        let _ = decode_header(data, start, start + lf);
+        start += lf + 1;
+    }
+
+    // And with bounds taken from the input itself, which can be anything.
+    if data.len() >= 2 {
+        let _ = decode_header(&data[2..], data[0] as usize, data[1] as usize);
+    }
+});
@@ -0,0 +1,339 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+use crate::breaker::BreakerState;
+use crate::config::Config;
+use crate::http::request::RawHttpRequest;
+use crate::http::target;
+use crate::proxy::Proxy;
+
+// Largest request the admin listener reads, its requests have no body.
+const MAX_REQUEST: usize = 8 * 1024;
+
+// Shown by GET /config in place of the values of rewritten headers.
+const REDACTED: &str = "<redacted>";
+
+// Serves the admin endpoints until the listener fails:
+// GET  /metrics                      the metrics in the Prometheus text format
+// GET  /status                       paused, draining and connections in flight
+// POST /pause, /resume               stop or restart mirroring to the shadows
+// POST /sampling?rate=R[&shadow=N]   set the sampling rate, of all shadows
+//                                    when no shadow is given
+// GET  /health                       results of the recent upstream requests
+// POST /drain                        stop accepting client connections
+// POST /reload                       reload the comparison rules from the
+//                                    config file
+// GET  /config                       the config with the runtime changes,
+//                                    rewritten header values redacted
+pub async fn serve(listener: TcpListener, proxy: Arc<Proxy>) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("admin listener stopped: {}", e);
+                return;
+            }
+        };
+        debug!("admin client connected: {}", addr);
+
+        let proxy = proxy.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &proxy).await {
+                debug!("admin client {} failed: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, proxy: &Proxy) -> Result<(), std::io::Error> {
+    // Same deadline as a client gets for its header section.
+    let timeout = Duration::from_millis(proxy.config.client_timeouts.header_ms);
+    let request = match tokio::time::timeout(timeout, read_request(&mut stream)).await {
+        Ok(request) => request?,
+        Err(_) => return Err(std::io::Error::from(std::io::ErrorKind::TimedOut)),
+    };
+
+    let response = match request.request_line() {
+        Ok(_) if !authorized(&request, proxy) => {
+            response("401 Unauthorized", "text/plain", String::from("unauthorized\n"))
+        }
+        Ok((method, target, _)) => {
+            let method = method.as_str();
+            let (path, query) = target.split_once('?').unwrap_or((target, ""));
+            route(method, path, query, proxy)
+        }
+        Err(_) => response("400 Bad Request", "text/plain", String::from("bad request\n")),
+    };
+
+    stream.write_all(&response).await?;
+    return stream.shutdown().await;
+}
+
+fn route(method: &str, path: &str, query: &str, proxy: &Proxy) -> Vec<u8> {
+    match (method, path) {
+        ("GET", "/metrics") => {
+            let breakers: Vec<_> = proxy
+                .config
+                .shadows
+                .iter()
+                .zip(proxy.breakers.iter())
+                .map(|(shadow, breaker)| (shadow.name.as_str(), breaker.state()))
+                .collect();
+            let body = proxy.metrics.render(&breakers);
+            return response("200 OK", "text/plain; version=0.0.4", body);
+        }
+        ("GET", "/status") => return ok(status(proxy)),
+        ("POST", "/pause") => {
+            info!("shadowing paused");
+            proxy.set_paused(true);
+            return ok(status(proxy));
+        }
+        ("POST", "/resume") => {
+            info!("shadowing resumed");
+            proxy.set_paused(false);
+            return ok(status(proxy));
+        }
+        ("POST", "/sampling") => return sampling(query, proxy),
+        ("GET", "/health") => return ok(health(proxy)),
+        ("POST", "/drain") => {
+            proxy.drain();
+            return ok(status(proxy));
+        }
+        ("POST", "/reload") => return reload(proxy),
+        ("GET", "/config") => return ok(effective_config(proxy)),
+        _ => return response("404 Not Found", "text/plain", String::from("not found\n")),
+    }
+}
+
+fn status(proxy: &Proxy) -> serde_json::Value {
+    json!({
+        "paused": proxy.paused(),
+        "draining": proxy.draining(),
+        "in_flight": proxy.in_flight.load(std::sync::atomic::Ordering::Relaxed),
+    })
+}
+
+fn sampling(query: &str, proxy: &Proxy) -> Vec<u8> {
+    let query = target::parse_query(query);
+    let param = |name: &str| query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+
+    let rate = match param("rate").and_then(|r| r.parse::<f64>().ok()) {
+        Some(rate) if (0.0..=1.0).contains(&rate) => rate,
+        _ => return bad_request("rate should be a number from 0 to 1"),
+    };
+
+    let shadows: Vec<usize> = match param("shadow") {
+        Some(name) => match proxy.config.shadows.iter().position(|s| s.name == name) {
+            Some(index) => vec![index],
+            None => return bad_request(&format!("unknown shadow {}", name)),
+        },
+        None => (0..proxy.config.shadows.len()).collect(),
+    };
+
+    for &index in shadows.iter() {
+        info!("sampling of {} set to {}", proxy.config.shadows[index].name, rate);
+        proxy.set_sampling(index, rate);
+    }
+    return ok(effective_config(proxy)["shadows"].clone());
+}
+
+fn health(proxy: &Proxy) -> serde_json::Value {
+    let mut mains: Vec<&str> = vec![proxy.config.main.as_str()];
+    for route in proxy.config.routes.iter() {
+        if let Some(main) = &route.main {
+            if !mains.contains(&main.as_str()) {
+                mains.push(main.as_str());
+            }
+        }
+    }
+
+    let mains: Vec<_> = mains
+        .into_iter()
+        .map(|address| json!({ "address": address, "health": proxy.metrics.health(address) }))
+        .collect();
+
+    let shadows: Vec<_> = proxy
+        .config
+        .shadows
+        .iter()
+        .zip(proxy.breakers.iter())
+        .map(|(shadow, breaker)| {
+            let breaker = match breaker.state() {
+                BreakerState::Closed => "closed",
+                BreakerState::Open => "open",
+                BreakerState::HalfOpen => "half_open",
+            };
+            json!({
+                "name": shadow.name,
+                "address": shadow.address,
+                "breaker": breaker,
+                "health": proxy.metrics.health(&shadow.address),
+            })
+        })
+        .collect();
+
+    json!({ "main": mains, "shadows": shadows })
+}
+
+// Reads the config file again and swaps in the comparison rules of every
+// shadow. Other changes to the file need a restart.
+fn reload(proxy: &Proxy) -> Vec<u8> {
+    let source = match &proxy.config.source {
+        Some(source) => source,
+        None => return response("409 Conflict", "text/plain", String::from("no config file to reload from\n")),
+    };
+
+    let reloaded = match Config::load(source) {
+        Ok(config) => config,
+        Err(e) => return bad_request(&e.to_string()),
+    };
+
+    let rules = proxy
+        .config
+        .shadows
+        .iter()
+        .map(|shadow| match reloaded.shadows.iter().find(|s| s.name == shadow.name) {
+            Some(s) => s.rules(&reloaded).clone(),
+            None => reloaded.compare.clone(),
+        })
+        .collect();
+    proxy.set_rules(rules);
+    info!("comparison rules reloaded from {}", source.display());
+
+    return ok(effective_config(proxy));
+}
+
+// The config the proxy started with, with the sampling rates and comparison
+// rules as they are now. The values of the headers set on shadow requests are
+// left out, they often hold credentials.
+fn effective_config(proxy: &Proxy) -> serde_json::Value {
+    let mut config = proxy.config.clone();
+    for (index, shadow) in config.shadows.iter_mut().enumerate() {
+        shadow.sampling = proxy.sampling(index);
+        shadow.compare = Some(proxy.rules(index).as_ref().clone());
+        for value in shadow.rewrite.set_headers.values_mut() {
+            *value = String::from(REDACTED);
+        }
+    }
+    return serde_json::to_value(&config).unwrap_or_default();
+}
+
+// Without a configured token every request is allowed.
+fn authorized(request: &RawHttpRequest, proxy: &Proxy) -> bool {
+    let token = match proxy.config.admin.as_ref().and_then(|a| a.token.as_ref()) {
+        Some(token) => token,
+        None => return true,
+    };
+    let given = match request.header("authorization").and_then(|h| h.strip_prefix("Bearer ")) {
+        Some(given) => given,
+        None => return false,
+    };
+
+    // Compares every byte so the time taken does not depend on where the
+    // token differs.
+    if given.len() != token.len() {
+        return false;
+    }
+    let diff = given.bytes().zip(token.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b));
+    return diff == 0;
+}
+
+fn ok(body: serde_json::Value) -> Vec<u8> {
+    let mut body = body.to_string();
+    body.push('\n');
+    return response("200 OK", "application/json", body);
+}
+
+fn bad_request(msg: &str) -> Vec<u8> {
+    return response("400 Bad Request", "text/plain", format!("{}\n", msg));
+}
+
+// Reads up to the end of the header section.
+async fn read_request(stream: &mut TcpStream) -> Result<RawHttpRequest, std::io::Error> {
+    let mut bytes = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    while !bytes.windows(4).any(|w| w == b"\r\n\r\n") && !bytes.windows(2).any(|w| w == b"\n\n") {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        bytes.extend_from_slice(&buf[..n]);
+        if bytes.len() > MAX_REQUEST {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "admin request too large"));
+        }
+    }
+
+    return Ok(RawHttpRequest::from(bytes));
+}
+
+fn response(status: &str, content_type: &str, body: String) -> Vec<u8> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(body.as_bytes());
+    return bytes;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::AdminConfig;
+
+    fn proxy(token: Option<&str>) -> Proxy {
+        let config = Config {
+            admin: Some(AdminConfig {
+                token: token.map(String::from),
+                ..AdminConfig::default()
+            }),
+            ..Config::default()
+        };
+        return Proxy::new(config).expect("valid config");
+    }
+
+    #[test]
+    fn token() {
+        let request = |auth: &str| RawHttpRequest::from(Vec::from(format!("GET /status HTTP/1.1\r\n{}\r\n\r\n", auth)));
+
+        assert!(authorized(&request(""), &proxy(None)));
+
+        let proxy = proxy(Some("secret"));
+        assert!(authorized(&request("Authorization: Bearer secret"), &proxy));
+        assert!(!authorized(&request("Authorization: Bearer secreT"), &proxy));
+        assert!(!authorized(&request("Authorization: secret"), &proxy));
+        assert!(!authorized(&request(""), &proxy));
+    }
+
+    #[test]
+    fn config_redacts_headers() {
+        let mut config = Config::default();
+        config.shadows[0].rewrite.set_headers.insert(String::from("Authorization"), String::from("Bearer secret"));
+        let proxy = Proxy::new(config).expect("valid config");
+
+        let shown = effective_config(&proxy);
+        assert_eq!(shown["shadows"][0]["rewrite"]["set_headers"]["Authorization"], REDACTED);
+        assert!(!shown.to_string().contains("secret"));
+    }
+
+    #[test]
+    fn set_sampling() {
+        let proxy = proxy(None);
+
+        let ok = sampling("shadow=shadow&rate=0.25", &proxy);
+        assert!(ok.starts_with(b"HTTP/1.1 200"));
+        assert_eq!(proxy.sampling(0), 0.25);
+
+        assert!(sampling("rate=2", &proxy).starts_with(b"HTTP/1.1 400"));
+        assert!(sampling("shadow=other&rate=0.5", &proxy).starts_with(b"HTTP/1.1 400"));
+        assert_eq!(proxy.sampling(0), 0.25);
+    }
+}
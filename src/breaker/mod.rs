@@ -0,0 +1,116 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::BreakerConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    // The cooldown passed and a probe request is in flight.
+    HalfOpen,
+}
+
+// Per shadow circuit breaker, requests are not mirrored to a shadow while its
+// breaker is open.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    failures: u32,
+    cooldown: Duration,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    failures: u32,
+    opened: Option<Instant>,
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: &BreakerConfig) -> CircuitBreaker {
+        CircuitBreaker {
+            failures: config.failures,
+            cooldown: Duration::from_millis(config.cooldown_ms),
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    // Whether a request may be sent to the shadow. Once the cooldown has
+    // passed this lets exactly one probe through until its result is
+    // recorded.
+    pub fn allow(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let opened = match inner.opened {
+            Some(opened) => opened,
+            None => return true,
+        };
+        if inner.probing || opened.elapsed() < self.cooldown {
+            return false;
+        }
+        inner.probing = true;
+        return true;
+    }
+
+    pub fn record(&self, success: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if success {
+            *inner = Inner::default();
+            return;
+        }
+
+        inner.failures = inner.failures.saturating_add(1);
+        if inner.probing || (self.failures > 0 && inner.failures >= self.failures) {
+            inner.opened = Some(Instant::now());
+            inner.probing = false;
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        let inner = self.inner.lock().unwrap();
+        if let None = inner.opened {
+            return BreakerState::Closed;
+        }
+        if inner.probing {
+            return BreakerState::HalfOpen;
+        }
+        return BreakerState::Open;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn opens_and_probes() {
+        let breaker = CircuitBreaker::new(&BreakerConfig { failures: 2, cooldown_ms: 0 });
+
+        breaker.record(false);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        breaker.record(false);
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        // The cooldown is over right away, one probe is let through.
+        assert!(breaker.allow());
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert!(!breaker.allow());
+
+        breaker.record(false);
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(breaker.allow());
+        breaker.record(true);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn disabled() {
+        let breaker = CircuitBreaker::new(&BreakerConfig { failures: 0, cooldown_ms: 0 });
+        for _ in 0..10 {
+            breaker.record(false);
+        }
+        assert!(breaker.allow());
+        assert_eq!(breaker.state(), BreakerState::Closed);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::compare::{self, MismatchClass};
+use crate::config::{CanaryConfig, CompareConfig, Config};
+use crate::http::{request::RawHttpRequest, response::RawHttpResponse};
+use crate::util::hash;
+
+#[derive(Debug)]
+enum Sticky {
+    Header(String),
+    Cookie(String),
+}
+
+// Decides which requests are answered by the canary shadow and whether its
+// response is good enough to be served.
+#[derive(Debug)]
+pub struct Canary {
+    // Index into Config::shadows.
+    pub shadow: usize,
+    fraction: f64,
+    critical: Vec<MismatchClass>,
+    sticky: Option<Sticky>,
+}
+
+impl Canary {
+    pub fn new(canary: &CanaryConfig, config: &Config) -> Result<Canary, String> {
+        let shadow = config
+            .shadows
+            .iter()
+            .position(|s| s.name == canary.shadow)
+            .ok_or_else(|| format!("canary refers to unknown shadow {}", canary.shadow))?;
+
+        let sticky = match (&canary.sticky_header, &canary.sticky_cookie) {
+            (Some(header), _) => Some(Sticky::Header(header.clone())),
+            (None, Some(cookie)) => Some(Sticky::Cookie(cookie.clone())),
+            (None, None) => None,
+        };
+
+        Ok(Canary {
+            shadow,
+            fraction: canary.fraction,
+            critical: canary.critical.clone(),
+            sticky,
+        })
+    }
+
+    // Whether the request should be answered by the canary. Requests with a
+    // sticky key always get the same answer for the same key, other requests
+    // are assigned at random.
+    pub fn assign(&self, request: &RawHttpRequest) -> bool {
+        let key = match &self.sticky {
+            Some(Sticky::Header(name)) => request.header(name),
+            Some(Sticky::Cookie(name)) => cookie(request, name),
+            None => None,
+        };
+
+        match key {
+            Some(key) => bucket(key) < self.fraction,
+            None => rand::random::<f64>() < self.fraction,
+        }
+    }
+
+    // The canary response may be served when it has none of the critical
+    // differences with main's response.
+    pub fn accept(&self, main: &RawHttpResponse, shadow: &RawHttpResponse, rules: &CompareConfig) -> bool {
+        let (main, shadow) = match (main.decode(), shadow.decode()) {
+            (Ok(m), Ok(s)) => (m, s),
+            _ => return false,
+        };
+
+        !compare::compare(&main, &shadow, rules)
+            .iter()
+            .any(|d| self.critical.contains(&d.class()))
+    }
+}
+
+fn cookie<'a>(request: &'a RawHttpRequest, name: &str) -> Option<&'a str> {
+    request.header("cookie")?.split(';').find_map(|pair| {
+        let (n, v) = pair.trim().split_once('=')?;
+        if n == name {
+            Some(v)
+        } else {
+            None
+        }
+    })
+}
+
+// Maps a key to a stable number in [0, 1) using FNV-1a, so assignments survive
+// restarts of the proxy.
+fn bucket(key: &str) -> f64 {
+    let hash = hash::fnv1a(key.as_bytes());
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::ShadowConfig;
+
+    fn canary(fraction: f64, sticky_cookie: Option<&str>) -> Canary {
+        let config = Config {
+            shadows: vec![ShadowConfig::default()],
+            ..Config::default()
+        };
+        let canary = CanaryConfig {
+            shadow: String::from("shadow"),
+            fraction,
+            sticky_cookie: sticky_cookie.map(String::from),
+            ..CanaryConfig::default()
+        };
+        Canary::new(&canary, &config).expect("valid canary")
+    }
+
+    #[test]
+    fn sticky_cookie_is_stable() {
+        let canary = canary(0.5, Some("session"));
+        let request = RawHttpRequest::from(Vec::from(
+            "GET / HTTP/1.1\r\nCookie: theme=dark; session=abc123\r\n\r\n",
+        ));
+        assert_eq!(cookie(&request, "session"), Some("abc123"));
+
+        let first = canary.assign(&request);
+        assert!((0..20).all(|_| canary.assign(&request) == first));
+    }
+
+    #[test]
+    fn fraction_bounds() {
+        let request = RawHttpRequest::from(Vec::from("GET / HTTP/1.1\r\n\r\n"));
+        assert!(!canary(0.0, None).assign(&request));
+        assert!(canary(1.0, None).assign(&request));
+    }
+
+    #[test]
+    fn critical_differences_fall_back() {
+        let canary = canary(1.0, None);
+        let rules = CompareConfig::default();
+        let main = RawHttpResponse::from(Vec::from("HTTP/1.1 200 OK\r\n\r\n{\"a\":1}"));
+        let body_differs = RawHttpResponse::from(Vec::from("HTTP/1.1 200 OK\r\n\r\n{\"a\":2}"));
+        let status_differs = RawHttpResponse::from(Vec::from("HTTP/1.1 500 Oops\r\n\r\n{\"a\":1}"));
+
+        assert!(canary.accept(&main, &body_differs, &rules));
+        assert!(!canary.accept(&main, &status_differs, &rules));
+    }
+}
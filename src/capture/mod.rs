@@ -0,0 +1,314 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use std::time::Duration;
+
+use chrono::{DateTime, TimeZone, Utc};
+use tokio::sync::mpsc::Receiver;
+
+use crate::http::{request::RawHttpRequest, response::RawHttpResponse};
+
+// A capture file holds recorded exchanges in the order they were handled. Each
+// record starts with a text line followed by the raw bytes of the messages:
+//
+// <unix millis> <request length> <response length or '-'> [<latency micros>]\n
+// <request bytes><response bytes>\n
+//
+// The response is the one main returned, it is absent for captures that only
+// hold requests. The latency of main is optional and only written when known.
+// The raw bytes are stored as is so that replaying sends exactly what the
+// client sent.
+
+#[derive(Debug)]
+pub struct CaptureRecord {
+    pub timestamp: DateTime<Utc>,
+    pub request: RawHttpRequest,
+    pub response: Option<RawHttpResponse>,
+}
+
+pub struct CaptureWriter<W: Write = File> {
+    file: BufWriter<W>,
+}
+
+impl CaptureWriter {
+    // Opens the capture file in append mode, creating it when needed.
+    pub fn open<T>(path: T) -> Result<CaptureWriter, std::io::Error>
+    where
+        T: AsRef<Path>,
+    {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(CaptureWriter {
+            file: BufWriter::new(file),
+        })
+    }
+
+    // Writes every record received on the channel until all senders are
+    // dropped. Runs apart from the parsing runtime so a slow disk does not
+    // hold up the proxy.
+    pub async fn run(mut self, mut rx: Receiver<CaptureRecord>) {
+        while let Some(record) = rx.recv().await {
+            if let Err(e) = self.write(record.timestamp, &record.request, record.response.as_ref()) {
+                tracing::error!("error writing capture: {}", e);
+            }
+        }
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn write(
+        &mut self,
+        timestamp: DateTime<Utc>,
+        request: &RawHttpRequest,
+        response: Option<&RawHttpResponse>,
+    ) -> Result<(), std::io::Error> {
+        let response_len = match response {
+            Some(r) => r.bytes.len().to_string(),
+            None => String::from("-"),
+        };
+
+        write!(
+            self.file,
+            "{} {} {}",
+            timestamp.timestamp_millis(),
+            request.bytes.len(),
+            response_len
+        )?;
+        if let Some(latency) = response.and_then(|r| r.latency) {
+            write!(self.file, " {}", latency.as_micros())?;
+        }
+        self.file.write_all(b"\n")?;
+        self.file.write_all(&request.bytes)?;
+        if let Some(r) = response {
+            self.file.write_all(&r.bytes)?;
+        }
+        self.file.write_all(b"\n")?;
+        self.file.flush()
+    }
+}
+
+pub struct CaptureReader<R> {
+    reader: R,
+    // Line of the file the next read starts on, for error messages.
+    line: usize,
+    // Set after a bad record. Lines are skipped until one reads as a record
+    // header, since the lengths of the bad record cannot be trusted.
+    resync: bool,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open<T>(path: T) -> Result<Self, std::io::Error>
+    where
+        T: AsRef<Path>,
+    {
+        Ok(CaptureReader::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+// The text line that starts a record.
+struct Header {
+    timestamp: DateTime<Utc>,
+    request_len: usize,
+    response_len: Option<usize>,
+    latency: Option<Duration>,
+}
+
+impl<R: BufRead> CaptureReader<R> {
+    pub fn new(reader: R) -> Self {
+        CaptureReader { reader, line: 1, resync: false }
+    }
+
+    fn read_record(&mut self) -> Result<Option<CaptureRecord>, std::io::Error> {
+        loop {
+            let start = self.line;
+            let mut line = Vec::new();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+
+            let header = match read_header(&line) {
+                Ok(header) => header,
+                Err(_) if self.resync => continue,
+                Err(e) => {
+                    self.resync = true;
+                    return Err(at_line(start, e));
+                }
+            };
+            self.resync = false;
+
+            return match self.read_messages(header) {
+                Ok(record) => Ok(Some(record)),
+                Err(e) => {
+                    self.resync = true;
+                    Err(at_line(start, e))
+                }
+            };
+        }
+    }
+
+    fn read_messages(&mut self, header: Header) -> Result<CaptureRecord, std::io::Error> {
+        let mut request = vec![0u8; header.request_len];
+        self.reader.read_exact(&mut request)?;
+        self.line += newlines(&request);
+
+        let response = match header.response_len {
+            Some(n) => {
+                let mut response = vec![0u8; n];
+                self.reader.read_exact(&mut response)?;
+                self.line += newlines(&response);
+                let mut response = RawHttpResponse::from(response);
+                response.latency = header.latency;
+                Some(response)
+            }
+            None => None,
+        };
+
+        let mut newline = [0u8; 1];
+        self.reader.read_exact(&mut newline)?;
+        self.line += newlines(&newline);
+        if newline[0] != b'\n' {
+            return Err(invalid(String::from("record is not terminated by a newline")));
+        }
+
+        Ok(CaptureRecord {
+            timestamp: header.timestamp,
+            request: RawHttpRequest::from(request),
+            response,
+        })
+    }
+}
+
+fn read_header(line: &[u8]) -> Result<Header, std::io::Error> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid(format!("bad record header: {:?}", String::from_utf8_lossy(line))))?;
+
+    let mut parts = line.split_whitespace();
+    let (millis, request_len, response_len) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(m), Some(rq), Some(rs)) => (m, rq, rs),
+        _ => return Err(invalid(format!("bad record header: {:?}", line))),
+    };
+
+    let millis: i64 = millis.parse().map_err(|_| invalid(format!("bad timestamp: {}", millis)))?;
+    let timestamp = match Utc.timestamp_millis_opt(millis) {
+        chrono::LocalResult::Single(t) => t,
+        _ => return Err(invalid(format!("bad timestamp: {}", millis))),
+    };
+
+    let request_len: usize = request_len
+        .parse()
+        .map_err(|_| invalid(format!("bad request length: {}", request_len)))?;
+    let response_len: Option<usize> = match response_len {
+        "-" => None,
+        n => Some(n.parse().map_err(|_| invalid(format!("bad response length: {}", n)))?),
+    };
+    let latency: Option<Duration> = match parts.next() {
+        Some(n) => Some(Duration::from_micros(
+            n.parse().map_err(|_| invalid(format!("bad latency: {}", n)))?,
+        )),
+        None => None,
+    };
+
+    Ok(Header {
+        timestamp,
+        request_len,
+        response_len,
+        latency,
+    })
+}
+
+fn newlines(bytes: &[u8]) -> usize {
+    bytes.iter().filter(|&&b| b == b'\n').count()
+}
+
+impl<R: BufRead> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+fn invalid(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+// Prefixes the error with the line the bad record starts on, keeping its kind.
+fn at_line(line: usize, e: std::io::Error) -> std::io::Error {
+    std::io::Error::new(e.kind(), format!("line {}: {}", line, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_records() {
+        let data = b"1700000000000 5 8\nGET /HTTP/1.1\n1700000000250 4 -\nPOST\n";
+        let mut reader = CaptureReader::new(&data[..]);
+
+        let first = reader.next().expect("first record").expect("valid record");
+        assert_eq!(first.timestamp.timestamp_millis(), 1700000000000);
+        assert_eq!(first.request.bytes, &b"GET /"[..]);
+        assert_eq!(first.response.expect("has response").bytes, &b"HTTP/1.1"[..]);
+
+        let second = reader.next().expect("second record").expect("valid record");
+        assert_eq!(second.request.bytes, &b"POST"[..]);
+        assert!(second.response.is_none());
+
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let mut file: Vec<u8> = Vec::new();
+        {
+            let mut writer = CaptureWriter { file: BufWriter::new(&mut file) };
+            let mut response = RawHttpResponse::from(Vec::from("HTTP/1.1 200 OK\r\n\r\n"));
+            response.latency = Some(Duration::from_micros(1500));
+            writer
+                .write(Utc::now(), &RawHttpRequest::from(Vec::from("GET / HTTP/1.1\r\n\r\n")), Some(&response))
+                .expect("writable");
+        }
+
+        let record = CaptureReader::new(&file[..])
+            .next()
+            .expect("a record")
+            .expect("valid record");
+        let response = record.response.expect("has response");
+        assert_eq!(response.bytes, &b"HTTP/1.1 200 OK\r\n\r\n"[..]);
+        assert_eq!(response.latency, Some(Duration::from_micros(1500)));
+    }
+
+    #[test]
+    fn truncated_record() {
+        let data = b"1700000000000 50 -\nGET /";
+        let mut reader = CaptureReader::new(&data[..]);
+        assert!(reader.next().expect("a record").is_err());
+    }
+
+    #[test]
+    fn skip_corrupt_record() {
+        // The second record claims a longer request than it holds, so its
+        // bytes are skipped up to the next record header.
+        let data = b"1700000000000 5 -\nGET /\n1700000000100 9 -\nGET /\r\nHost: a\r\n\n1700000000200 4 -\nPOST\n";
+        let mut reader = CaptureReader::new(&data[..]);
+
+        assert!(reader.next().expect("first record").is_ok());
+        let e = reader.next().expect("second record").expect_err("corrupt record");
+        assert!(e.to_string().starts_with("line 3: "), "{}", e);
+        let third = reader.next().expect("third record").expect("valid record");
+        assert_eq!(third.request.bytes, &b"POST"[..]);
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn skip_unreadable_header() {
+        let data = b"\xff\xfe\n1700000000000 4 -\nPOST\n";
+        let mut reader = CaptureReader::new(&data[..]);
+
+        let e = reader.next().expect("a record").expect_err("unreadable header");
+        assert!(e.to_string().starts_with("line 1: "), "{}", e);
+        assert!(reader.next().expect("a record").is_ok());
+        assert!(reader.next().is_none());
+    }
+}
@@ -0,0 +1,661 @@
+use std::collections::BTreeMap;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::CompareConfig;
+use crate::routing::template::Templates;
+use crate::http::{
+    error::ServerError,
+    request::{RawHttpRequest, REQUEST_ID_HEADER},
+    partials::HttpHeader,
+    response::{DecodedHttpResponse, RawHttpResponse},
+    tee::BodyDigest,
+};
+use crate::util::hash;
+
+// Values in a difference are cut off at this many characters so a single
+// large body does not blow up the result store.
+const MAX_VALUE_LEN: usize = 256;
+
+// Bytes shown on either side of the first differing byte of hashed bodies.
+const HEX_CONTEXT: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Match,
+    Mismatch,
+    // The exchange could not be compared, e.g. the shadow did not respond or
+    // one of the messages could not be decoded.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Difference {
+    Status {
+        main: Option<u16>,
+        shadow: Option<u16>,
+    },
+    Header {
+        name: String,
+        main: Option<String>,
+        shadow: Option<String>,
+    },
+    // path is a JSON path like $.items[0].id when both bodies are JSON, and $
+    // for any other body that differs.
+    Body {
+        path: String,
+        main: Option<String>,
+        shadow: Option<String>,
+    },
+    // Bodies that are compared by length and SHA-256 only. offset is the
+    // first byte that differs when it is known, the contexts are the bytes
+    // around it in hex with the differing byte in brackets.
+    Bytes {
+        main: BodyDigest,
+        shadow: BodyDigest,
+        offset: Option<usize>,
+        #[serde(default)]
+        main_context: String,
+        #[serde(default)]
+        shadow_context: String,
+    },
+}
+
+// The kind of a difference, used to mark some kinds of differences as more
+// important than others.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MismatchClass {
+    Status,
+    Header,
+    Body,
+}
+
+impl Difference {
+    pub fn class(&self) -> MismatchClass {
+        match self {
+            Difference::Status { .. } => MismatchClass::Status,
+            Difference::Header { .. } => MismatchClass::Header,
+            Difference::Body { .. } | Difference::Bytes { .. } => MismatchClass::Body,
+        }
+    }
+}
+
+// The result of comparing one exchange, this is what ends up in the store.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComparisonRecord {
+    pub timestamp: DateTime<Utc>,
+    // Correlation ID of the exchange, the X-Request-ID sent upstream.
+    #[serde(default)]
+    pub request_id: String,
+    // Name of the shadow that was compared against main.
+    #[serde(default)]
+    pub shadow: String,
+    // Name of the route the request matched.
+    #[serde(default)]
+    pub route: String,
+    pub method: String,
+    pub target: String,
+    // The target as a route template like /users/{id}, results are
+    // aggregated by this instead of the target.
+    #[serde(default)]
+    pub template: String,
+    pub outcome: Outcome,
+    pub main_status: Option<u16>,
+    pub shadow_status: Option<u16>,
+    pub main_latency_ms: Option<f64>,
+    pub shadow_latency_ms: Option<f64>,
+    pub differences: Vec<Difference>,
+    // Identifies mismatches that fail in the same way, see
+    // ComparisonRecord::cluster.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    pub error: Option<String>,
+    // The client was answered with the shadow response (canary mode).
+    #[serde(default)]
+    pub canary: bool,
+    // The raw messages, only kept for mismatches so they can be inspected or
+    // exported later without storing every exchange.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exchange: Option<Exchange>,
+}
+
+// Shares the buffers of the raw messages, keeping them costs no copy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    #[serde(with = "crate::util::base64")]
+    pub request: Bytes,
+    #[serde(with = "crate::util::base64")]
+    pub main: Bytes,
+    #[serde(with = "crate::util::base64")]
+    pub shadow: Bytes,
+}
+
+// Decodes the request and both responses and compares them. Decoding issues
+// and shadow errors do not fail, they are recorded with the Error outcome.
+pub fn evaluate(
+    shadow: &str,
+    route: &str,
+    templates: &Templates,
+    request: RawHttpRequest,
+    main_response: RawHttpResponse,
+    shadow_response: Result<RawHttpResponse, ServerError>,
+    config: &CompareConfig,
+) -> ComparisonRecord {
+    let main_latency_ms = main_response.latency.map(|d| d.as_secs_f64() * 1000.0);
+
+    let mut record = ComparisonRecord {
+        timestamp: Utc::now(),
+        request_id: String::from(request.header(REQUEST_ID_HEADER).unwrap_or_default()),
+        shadow: String::from(shadow),
+        route: String::from(route),
+        method: String::new(),
+        target: String::new(),
+        template: String::new(),
+        outcome: Outcome::Error,
+        main_status: None,
+        shadow_status: None,
+        main_latency_ms,
+        shadow_latency_ms: None,
+        differences: Vec::new(),
+        signature: None,
+        error: None,
+        canary: false,
+        exchange: None,
+    };
+
+    let request_bytes = request.bytes.clone();
+    let main_bytes = main_response.bytes.clone();
+
+    match request.decode() {
+        Ok(r) => {
+            record.method = String::from(r.method().as_str());
+            record.template = templates.normalize(&r.target.origin());
+            record.target = String::from(r.raw_target());
+        }
+        Err(e) => {
+            record.error = Some(format!("error parsing request: {}", e));
+            return record;
+        }
+    }
+
+    let main_parsed = match main_response.decode() {
+        Ok(m) => m,
+        Err(e) => {
+            record.error = Some(format!("error parsing main response: {}", e));
+            return record;
+        }
+    };
+    record.main_status = main_parsed.status.code();
+
+    let shadow_response = match shadow_response {
+        Ok(s) => s,
+        Err(e) => {
+            record.error = Some(format!("error with shadow: {}", e));
+            return record;
+        }
+    };
+    record.shadow_latency_ms = shadow_response.latency.map(|d| d.as_secs_f64() * 1000.0);
+    let shadow_bytes = shadow_response.bytes.clone();
+
+    let shadow_parsed = match shadow_response.decode() {
+        Ok(s) => s,
+        Err(e) => {
+            record.error = Some(format!("error parsing shadow response: {}", e));
+            return record;
+        }
+    };
+    record.shadow_status = shadow_parsed.status.code();
+
+    record.differences = compare(&main_parsed, &shadow_parsed, config);
+    if record.differences.is_empty() {
+        record.outcome = Outcome::Match;
+    } else {
+        record.outcome = Outcome::Mismatch;
+        record.signature = Some(record.signature());
+        let mut exchange = Exchange {
+            request: request_bytes,
+            main: main_bytes,
+            shadow: shadow_bytes,
+        };
+        // Hashed bodies are not worth keeping, only the heads are.
+        if hashed(&main_parsed, config) || hashed(&shadow_parsed, config) {
+            exchange.main = exchange.main.slice(..main_parsed.head);
+            exchange.shadow = exchange.shadow.slice(..shadow_parsed.head);
+        }
+        record.exchange = Some(exchange);
+    }
+
+    return record;
+}
+
+impl ComparisonRecord {
+    // Describes how the exchange failed without the details that differ
+    // between requests: the route template, the status pair and which headers
+    // and JSON paths differ, with array indices left out. Mismatches with the
+    // same description are most likely the same bug.
+    pub fn cluster(&self) -> String {
+        let status = |code: Option<u16>| code.map(|c| c.to_string()).unwrap_or_else(|| String::from("?"));
+
+        let mut fields: Vec<String> = self
+            .differences
+            .iter()
+            .filter_map(|d| match d {
+                Difference::Status { .. } => None,
+                Difference::Header { name, .. } => Some(format!("header:{}", name.to_ascii_lowercase())),
+                Difference::Body { path, .. } => Some(format!("body:{}", without_indices(path))),
+                Difference::Bytes { .. } => Some(String::from("body:bytes")),
+            })
+            .collect();
+        fields.sort();
+        fields.dedup();
+
+        let mut cluster = format!(
+            "{} {} {}->{}",
+            self.method,
+            self.template(),
+            status(self.main_status),
+            status(self.shadow_status)
+        );
+        for field in fields {
+            cluster.push(' ');
+            cluster.push_str(&field);
+        }
+        return cluster;
+    }
+
+    // The stored template, or one inferred from the target for records that
+    // were stored without it.
+    pub fn template(&self) -> String {
+        if self.template.is_empty() {
+            return Templates::default().normalize(&self.target);
+        }
+        return self.template.clone();
+    }
+
+    // Short hash of the cluster description, stored with mismatches.
+    pub fn signature(&self) -> String {
+        format!("{:016x}", hash::fnv1a(self.cluster().as_bytes()))
+    }
+}
+
+// $.items[3].id becomes $.items[*].id
+fn without_indices(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    let mut index = false;
+    for c in path.chars() {
+        match c {
+            '[' => {
+                index = true;
+                out.push_str("[*");
+            }
+            ']' => {
+                index = false;
+                out.push(']');
+            }
+            _ if index => {}
+            _ => out.push(c),
+        }
+    }
+    return out;
+}
+
+pub fn compare(
+    main: &DecodedHttpResponse,
+    shadow: &DecodedHttpResponse,
+    config: &CompareConfig,
+) -> Vec<Difference> {
+    let mut differences: Vec<Difference> = Vec::new();
+
+    if main.status != shadow.status {
+        differences.push(Difference::Status {
+            main: main.status.code(),
+            shadow: shadow.status.code(),
+        });
+    }
+
+    compare_headers(main, shadow, config, &mut differences);
+    match hashed(main, config) || hashed(shadow, config) {
+        true => compare_hashed(main, shadow, &mut differences),
+        false => compare_bodies(&main.body, &shadow.body, &mut differences),
+    }
+
+    return differences;
+}
+
+fn header_map(response: &DecodedHttpResponse, config: &CompareConfig) -> BTreeMap<String, String> {
+    let mut map: BTreeMap<String, String> = BTreeMap::new();
+
+    for (header, value) in response.headers() {
+        let name: &str = header.into();
+        if config.ignore_headers.iter().any(|i| i.eq_ignore_ascii_case(name)) {
+            continue;
+        }
+        map.entry(String::from(name))
+            .and_modify(|v| {
+                v.push_str(", ");
+                v.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+
+    return map;
+}
+
+fn compare_headers(
+    main: &DecodedHttpResponse,
+    shadow: &DecodedHttpResponse,
+    config: &CompareConfig,
+    differences: &mut Vec<Difference>,
+) {
+    let main_headers = header_map(main, config);
+    let mut shadow_headers = header_map(shadow, config);
+
+    for (name, main_value) in main_headers.into_iter() {
+        let shadow_value = shadow_headers.remove(&name);
+        if shadow_value.as_ref() != Some(&main_value) {
+            differences.push(Difference::Header {
+                name,
+                main: Some(main_value),
+                shadow: shadow_value,
+            });
+        }
+    }
+
+    for (name, shadow_value) in shadow_headers.into_iter() {
+        differences.push(Difference::Header {
+            name,
+            main: None,
+            shadow: Some(shadow_value),
+        });
+    }
+}
+
+fn compare_bodies(main: &[u8], shadow: &[u8], differences: &mut Vec<Difference>) {
+    if main == shadow {
+        return;
+    }
+
+    let main_json = serde_json::from_slice::<Value>(main);
+    let shadow_json = serde_json::from_slice::<Value>(shadow);
+
+    if let (Ok(m), Ok(s)) = (main_json, shadow_json) {
+        json_diff(String::from("$"), Some(&m), Some(&s), differences);
+        return;
+    }
+
+    differences.push(Difference::Body {
+        path: String::from("$"),
+        main: Some(truncate(String::from_utf8_lossy(main).into_owned())),
+        shadow: Some(truncate(String::from_utf8_lossy(shadow).into_owned())),
+    });
+}
+
+// Whether the body is compared by its digest: it was larger than the tee
+// limit, is larger than hash_above or has one of the hashed content types.
+fn hashed(response: &DecodedHttpResponse, config: &CompareConfig) -> bool {
+    if response.digest.is_some() || response.body.len() > config.hash_above {
+        return true;
+    }
+    let content_type = match response.header(&HttpHeader::ContentType) {
+        Some(value) => value.trim().to_ascii_lowercase(),
+        None => return false,
+    };
+    return config
+        .hash_content_types
+        .iter()
+        .any(|t| content_type.starts_with(&t.to_ascii_lowercase()));
+}
+
+fn compare_hashed(main: &DecodedHttpResponse, shadow: &DecodedHttpResponse, differences: &mut Vec<Difference>) {
+    let main_digest = main.digest.clone().unwrap_or_else(|| BodyDigest::of(&main.body));
+    let shadow_digest = shadow.digest.clone().unwrap_or_else(|| BodyDigest::of(&shadow.body));
+    if main_digest == shadow_digest {
+        return;
+    }
+
+    let offset = first_difference(main, shadow);
+    let context = |body: &[u8]| offset.map(|o| hex_context(body, o)).unwrap_or_default();
+    differences.push(Difference::Bytes {
+        offset,
+        main_context: context(&main.body),
+        shadow_context: context(&shadow.body),
+        main: main_digest,
+        shadow: shadow_digest,
+    });
+}
+
+// Offset of the first byte that differs. A body larger than the tee limit is
+// only known up to where it was cut off, past that the offset is unknown.
+fn first_difference(main: &DecodedHttpResponse, shadow: &DecodedHttpResponse) -> Option<usize> {
+    if let Some(offset) = main.body.iter().zip(shadow.body.iter()).position(|(m, s)| m != s) {
+        return Some(offset);
+    }
+    // Equal as far as both go, so they differ where the shorter one ends
+    // when that is the real end of the body.
+    let end = std::cmp::min(main.body.len(), shadow.body.len());
+    let complete = |r: &DecodedHttpResponse| r.digest.is_none() && r.body.len() == end;
+    if complete(main) || complete(shadow) {
+        return Some(end);
+    }
+    return None;
+}
+
+// 00 01 [02] 03, with "[]" when the body ends at the offset.
+fn hex_context(body: &[u8], offset: usize) -> String {
+    let start = offset.saturating_sub(HEX_CONTEXT);
+    let end = std::cmp::min(body.len(), offset + HEX_CONTEXT + 1);
+    let mut parts: Vec<String> = Vec::new();
+    for (i, byte) in body.iter().enumerate().take(end).skip(start) {
+        match i == offset {
+            true => parts.push(format!("[{:02x}]", byte)),
+            false => parts.push(format!("{:02x}", byte)),
+        }
+    }
+    if offset >= body.len() {
+        parts.push(String::from("[]"));
+    }
+    return parts.join(" ");
+}
+
+// Walks both JSON documents and records every path where they differ. Objects
+// are compared per key and arrays per index, anything else by value.
+fn json_diff(path: String, main: Option<&Value>, shadow: Option<&Value>, differences: &mut Vec<Difference>) {
+    match (main, shadow) {
+        (Some(Value::Object(m)), Some(Value::Object(s))) => {
+            for (key, value) in m.iter() {
+                json_diff(format!("{}.{}", path, key), Some(value), s.get(key), differences);
+            }
+            for (key, value) in s.iter() {
+                if !m.contains_key(key) {
+                    json_diff(format!("{}.{}", path, key), None, Some(value), differences);
+                }
+            }
+        }
+        (Some(Value::Array(m)), Some(Value::Array(s))) => {
+            for i in 0..std::cmp::max(m.len(), s.len()) {
+                json_diff(format!("{}[{}]", path, i), m.get(i), s.get(i), differences);
+            }
+        }
+        (m, s) if m == s => {}
+        (m, s) => differences.push(Difference::Body {
+            path,
+            main: m.map(|v| truncate(v.to_string())),
+            shadow: s.map(|v| truncate(v.to_string())),
+        }),
+    }
+}
+
+fn truncate(mut value: String) -> String {
+    if value.len() > MAX_VALUE_LEN {
+        let mut end = MAX_VALUE_LEN;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+        value.push_str("...");
+    }
+    return value;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn response(payload: &str) -> DecodedHttpResponse {
+        RawHttpResponse::from(Vec::from(payload))
+            .decode()
+            .expect("decoding should work")
+    }
+
+    #[test]
+    fn identical_responses() {
+        let main = response("HTTP/1.1 200 OK\r\n\r\n{\"a\":1}");
+        let shadow = response("HTTP/1.1 200 OK\r\n\r\n{\"a\":1}");
+        assert!(compare(&main, &shadow, &CompareConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn status_difference() {
+        let main = response("HTTP/1.1 200 OK\r\n\r\n");
+        let shadow = response("HTTP/1.1 500 Internal Server Error\r\n\r\n");
+        let differences = compare(&main, &shadow, &CompareConfig::default());
+        assert_eq!(
+            differences,
+            vec![Difference::Status {
+                main: Some(200),
+                shadow: Some(500)
+            }]
+        );
+    }
+
+    #[test]
+    fn json_paths() {
+        let main = response("HTTP/1.1 200 OK\r\n\r\n{\"user\":{\"name\":\"a\"},\"items\":[1,2]}");
+        let shadow = response("HTTP/1.1 200 OK\r\n\r\n{\"items\":[1,3],\"user\":{\"name\":\"a\",\"age\":4}}");
+        let differences = compare(&main, &shadow, &CompareConfig::default());
+        let paths: Vec<&str> = differences
+            .iter()
+            .map(|d| match d {
+                Difference::Body { path, .. } => path.as_str(),
+                _ => "",
+            })
+            .collect();
+        assert_eq!(paths, vec!["$.items[1]", "$.user.age"]);
+    }
+
+    #[test]
+    fn raw_body_difference() {
+        let main = response("HTTP/1.1 200 OK\r\n\r\nThis is some binary data\n");
+        let shadow = response("HTTP/1.1 200 OK\r\n\r\nThis is some binary data");
+        let differences = compare(&main, &shadow, &CompareConfig::default());
+        assert_eq!(differences.len(), 1);
+        assert!(matches!(&differences[0], Difference::Body { path, .. } if path == "$"));
+    }
+
+    #[test]
+    fn digest_difference() {
+        let mut main = response("HTTP/1.1 200 OK\r\n\r\nlarge");
+        main.digest = Some(BodyDigest::of(b"large body"));
+        let shadow = response("HTTP/1.1 200 OK\r\n\r\nlarge body");
+        assert!(compare(&main, &shadow, &CompareConfig::default()).is_empty());
+
+        // Only the start of main is known, and it is the same.
+        let shadow = response("HTTP/1.1 200 OK\r\n\r\nlarge bodies");
+        let differences = compare(&main, &shadow, &CompareConfig::default());
+        assert!(matches!(
+            &differences[0],
+            Difference::Bytes { shadow, offset: None, .. } if shadow.length == 12
+        ));
+    }
+
+    #[test]
+    fn hashed_content_types() {
+        let main = response("HTTP/1.1 200 OK\r\nContent-Type: image/png\r\n\r\n0123456789abcdefghij");
+        let shadow = response("HTTP/1.1 200 OK\r\nContent-Type: image/png\r\n\r\n0123456789abcdefGHij");
+        let differences = compare(&main, &shadow, &CompareConfig::default());
+        assert_eq!(
+            differences,
+            vec![Difference::Bytes {
+                main: BodyDigest::of(b"0123456789abcdefghij"),
+                shadow: BodyDigest::of(b"0123456789abcdefGHij"),
+                offset: Some(16),
+                main_context: String::from("38 39 61 62 63 64 65 66 [67] 68 69 6a"),
+                shadow_context: String::from("38 39 61 62 63 64 65 66 [47] 48 69 6a"),
+            }]
+        );
+
+        let shadow = response("HTTP/1.1 200 OK\r\nContent-Type: image/png\r\n\r\n0123");
+        let differences = compare(&main, &shadow, &CompareConfig::default());
+        assert!(matches!(
+            &differences[0],
+            Difference::Bytes { offset: Some(4), shadow_context, .. } if shadow_context == "30 31 32 33 []"
+        ));
+    }
+
+    #[test]
+    fn hashed_above_size() {
+        let config = CompareConfig {
+            hash_above: 4,
+            ..CompareConfig::default()
+        };
+        let main = response("HTTP/1.1 200 OK\r\n\r\n{\"a\":1}");
+        let shadow = response("HTTP/1.1 200 OK\r\n\r\n{\"a\":2}");
+        let differences = compare(&main, &shadow, &config);
+        assert!(matches!(&differences[0], Difference::Bytes { offset: Some(5), .. }));
+        assert!(matches!(&compare(&main, &shadow, &CompareConfig::default())[0], Difference::Body { .. }));
+    }
+
+    #[test]
+    fn hashed_bodies_are_not_stored() {
+        let request = RawHttpRequest::from(Vec::from("GET /file HTTP/1.1\r\n\r\n"));
+        let main = RawHttpResponse::from(Vec::from("HTTP/1.1 200 OK\r\nContent-Type: application/pdf\r\n\r\n%PDF-1"));
+        let shadow = RawHttpResponse::from(Vec::from("HTTP/1.1 200 OK\r\nContent-Type: application/pdf\r\n\r\n%PDF-2"));
+        let record = evaluate("s", "default", &Templates::default(), request, main, Ok(shadow), &CompareConfig::default());
+        assert_eq!(record.outcome, Outcome::Mismatch);
+        assert_eq!(record.cluster(), "GET /file 200->200 body:bytes");
+        let exchange = record.exchange.unwrap();
+        assert_eq!(exchange.main, &b"HTTP/1.1 200 OK\r\nContent-Type: application/pdf\r\n\r\n"[..]);
+    }
+
+    #[test]
+    fn shadow_error_is_recorded() {
+        let request = RawHttpRequest::from(Vec::from("GET /api HTTP/1.1\r\nX-Request-ID: 42\r\n\r\n"));
+        let main = RawHttpResponse::from(Vec::from("HTTP/1.1 200 OK\r\n\r\n"));
+        let shadow = Err(ServerError::Unresponsive(
+            String::from("shadow"),
+            Box::new(std::io::Error::from(std::io::ErrorKind::ConnectionRefused)),
+        ));
+        let record = evaluate("shadow", "default", &Templates::default(), request, main, shadow, &CompareConfig::default());
+        assert_eq!(record.outcome, Outcome::Error);
+        assert_eq!(record.target, "/api");
+        assert_eq!(record.request_id, "42");
+        assert_eq!(record.main_status, Some(200));
+        assert!(record.error.is_some());
+        assert_eq!(record.signature, None);
+    }
+
+    #[test]
+    fn clusters() {
+        let request = || RawHttpRequest::from(Vec::from("GET /users/1 HTTP/1.1\r\n\r\n"));
+        let main = || RawHttpResponse::from(Vec::from("HTTP/1.1 200 OK\r\n\r\n{\"items\": [{\"id\": 1}, {\"id\": 2}]}"));
+        let shadow = |body: &str| Ok(RawHttpResponse::from(Vec::from(format!("HTTP/1.1 200 OK\r\n\r\n{}", body))));
+        let config = CompareConfig::default();
+        let t = Templates::default();
+
+        let a = evaluate("s", "users", &t, request(), main(), shadow(r#"{"items": [{"id": 3}, {"id": 2}]}"#), &config);
+        let b = evaluate("s", "users", &t, request(), main(), shadow(r#"{"items": [{"id": 1}, {"id": 4}]}"#), &config);
+        let c = evaluate("s", "users", &t, request(), main(), shadow(r#"{"items": []}"#), &config);
+
+        assert_eq!(a.template, "/users/{id}");
+        assert_eq!(a.cluster(), "GET /users/{id} 200->200 body:$.items[*].id");
+        assert!(a.signature.is_some());
+        assert_eq!(a.signature, b.signature);
+        assert_ne!(a.signature, c.signature);
+    }
+}
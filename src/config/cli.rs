@@ -0,0 +1,239 @@
+use std::path::PathBuf;
+
+use chrono::Utc;
+
+use crate::config::{Config, Mode, ShadowConfig};
+use crate::report;
+
+const USAGE: &str = "\
+usage: shadowapi [--config <file>] [--mode <mirror|capture>] [--capture <file>]
+       shadowapi replay <capture> [--config <file>] [--main <addr>] [--shadow <addr>]
+                        [--rate <req/s>] [--concurrency <n>] [--time-scale <factor>]
+       shadowapi compare <capture> [--config <file>] [--shadow <addr>]
+                         [--rate <req/s>] [--concurrency <n>] [--time-scale <factor>]
+       shadowapi export-har (<capture> | --mismatches) [--config <file>] [--output <file>]
+       shadowapi report [--config <file>] [--html <file>] [--markdown <file>]
+                        [--since <time>] [--until <time>] [--route <name>] [--shadow-name <name>]
+
+A replay input ending in .har is read as a HAR file, anything else as a capture.
+Report times are RFC 3339 or a duration before now like 30m, 12h or 7d. Without
+--html or --markdown the Markdown report is written to stdout.";
+
+// Flags that do not take a value.
+const SWITCHES: [&str; 1] = ["mismatches"];
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    // Run the proxy, mirroring live traffic to the shadow.
+    Proxy,
+    // Send the requests from a capture or HAR file to main and shadow and
+    // compare.
+    Replay(PathBuf),
+    // Send the requests from a capture file to the shadow only and compare
+    // with the main responses recorded in it.
+    Compare(PathBuf),
+    // Write captured exchanges or stored mismatches as HAR, to the output
+    // file or stdout.
+    ExportHar(HarSource, Option<PathBuf>),
+    // Summarize the comparison results in the configured result store.
+    Report(report::Options),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HarSource {
+    Capture(PathBuf),
+    // The mismatches in the configured result store.
+    Mismatches,
+}
+
+// Parses the command line (without the program name) into the command to run
+// and the configuration for it. Flags override values from the config file.
+pub fn parse<I>(args: I) -> Result<(Command, Config), String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter().peekable();
+
+    let subcommand = match args.peek() {
+        Some(a) if !a.starts_with("--") => args.next(),
+        _ => None,
+    };
+
+    let mut positional: Vec<String> = Vec::new();
+    let mut flags: Vec<(String, String)> = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if arg == "--help" || arg == "-h" {
+            return Err(String::from(USAGE));
+        }
+        if let Some(name) = arg.strip_prefix("--") {
+            if SWITCHES.contains(&name) {
+                flags.push((String::from(name), String::new()));
+                continue;
+            }
+            match args.next() {
+                Some(value) => flags.push((String::from(name), value)),
+                None => return Err(format!("missing value for --{}\n{}", name, USAGE)),
+            }
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    let mut config = match flags.iter().find(|(name, _)| name == "config") {
+        Some((_, path)) => Config::load(path).map_err(|e| e.to_string())?,
+        None => Config::default(),
+    };
+
+    let mut output: Option<PathBuf> = None;
+    let mut mismatches = false;
+    let mut report = report::Options::default();
+
+    for (name, value) in flags.iter() {
+        match name.as_str() {
+            "config" => {}
+            "output" => output = Some(PathBuf::from(value)),
+            "mismatches" => mismatches = true,
+            "html" => report.html = Some(PathBuf::from(value)),
+            "markdown" => report.markdown = Some(PathBuf::from(value)),
+            "since" => report.since = Some(report::parse_time(value, Utc::now())?),
+            "until" => report.until = Some(report::parse_time(value, Utc::now())?),
+            "route" => report.route = Some(value.clone()),
+            "shadow-name" => report.shadow = Some(value.clone()),
+            "main" => config.main = value.clone(),
+            // A shadow given on the command line replaces the configured ones.
+            "shadow" => {
+                config.shadows = vec![ShadowConfig {
+                    address: value.clone(),
+                    ..ShadowConfig::default()
+                }]
+            }
+            "capture" => config.capture = Some(PathBuf::from(value)),
+            "mode" => {
+                config.mode = match value.as_str() {
+                    "mirror" => Mode::Mirror,
+                    "capture" => Mode::Capture,
+                    _ => return Err(format!("invalid value for --mode: {}", value)),
+                }
+            }
+            "rate" => config.replay.rate = Some(parse_number(name, value)?),
+            "concurrency" => config.replay.concurrency = parse_number(name, value)?,
+            "time-scale" => config.replay.time_scale = Some(parse_number(name, value)?),
+            _ => return Err(format!("unknown flag --{}\n{}", name, USAGE)),
+        }
+    }
+
+    config.validate()?;
+
+    let command = match subcommand.as_deref() {
+        None | Some("proxy") => Command::Proxy,
+        Some("replay") => match positional.pop() {
+            Some(capture) => Command::Replay(PathBuf::from(capture)),
+            None => return Err(format!("replay needs a capture file\n{}", USAGE)),
+        },
+        Some("compare") => match positional.pop() {
+            Some(capture) => Command::Compare(PathBuf::from(capture)),
+            None => return Err(format!("compare needs a capture file\n{}", USAGE)),
+        },
+        Some("export-har") => match (positional.pop(), mismatches) {
+            (None, true) => Command::ExportHar(HarSource::Mismatches, output),
+            (Some(capture), false) => Command::ExportHar(HarSource::Capture(PathBuf::from(capture)), output),
+            _ => return Err(format!("export-har needs either a capture file or --mismatches\n{}", USAGE)),
+        },
+        Some("report") => Command::Report(report),
+        Some(other) => return Err(format!("unknown command {}\n{}", other, USAGE)),
+    };
+
+    Ok((command, config))
+}
+
+fn parse_number<T>(name: &str, value: &str) -> Result<T, String>
+where
+    T: std::str::FromStr,
+{
+    value
+        .parse::<T>()
+        .map_err(|_| format!("invalid value for --{}: {}", name, value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn no_arguments_runs_proxy() {
+        let (command, config) = parse(args("")).expect("should parse");
+        assert_eq!(command, Command::Proxy);
+        assert_eq!(config.main, "127.0.0.1:4001");
+    }
+
+    #[test]
+    fn replay_with_flags() {
+        let (command, config) =
+            parse(args("replay traffic.cap --rate 20 --concurrency 4 --time-scale 0.5"))
+                .expect("should parse");
+        assert_eq!(command, Command::Replay(PathBuf::from("traffic.cap")));
+        assert_eq!(config.replay.rate, Some(20.0));
+        assert_eq!(config.replay.concurrency, 4);
+        assert_eq!(config.replay.time_scale, Some(0.5));
+    }
+
+    #[test]
+    fn replay_without_capture() {
+        assert!(parse(args("replay --rate 20")).is_err());
+    }
+
+    #[test]
+    fn capture_mode() {
+        let (command, config) = parse(args("--mode capture --capture t.cap")).expect("should parse");
+        assert_eq!(command, Command::Proxy);
+        assert_eq!(config.mode, Mode::Capture);
+        assert_eq!(config.capture, Some(PathBuf::from("t.cap")));
+
+        let (command, _) = parse(args("compare t.cap")).expect("should parse");
+        assert_eq!(command, Command::Compare(PathBuf::from("t.cap")));
+
+        assert!(parse(args("--mode shadow-only")).is_err());
+    }
+
+    #[test]
+    fn export_har() {
+        let (command, _) = parse(args("export-har --mismatches --output m.har")).expect("should parse");
+        assert_eq!(
+            command,
+            Command::ExportHar(HarSource::Mismatches, Some(PathBuf::from("m.har")))
+        );
+
+        let (command, _) = parse(args("export-har traffic.cap")).expect("should parse");
+        assert_eq!(
+            command,
+            Command::ExportHar(HarSource::Capture(PathBuf::from("traffic.cap")), None)
+        );
+
+        assert!(parse(args("export-har traffic.cap --mismatches")).is_err());
+    }
+
+    #[test]
+    fn report_filters() {
+        let (command, _) = parse(args("report --html r.html --since 2024-05-01T00:00:00Z --shadow-name a"))
+            .expect("should parse");
+        let expected = report::Options {
+            html: Some(PathBuf::from("r.html")),
+            since: report::parse_time("2024-05-01T00:00:00Z", Utc::now()).ok(),
+            shadow: Some(String::from("a")),
+            ..report::Options::default()
+        };
+        assert_eq!(command, Command::Report(expected));
+
+        assert!(parse(args("report --since soon")).is_err());
+    }
+
+    #[test]
+    fn bad_number() {
+        assert!(parse(args("replay x.cap --concurrency many")).is_err());
+    }
+}
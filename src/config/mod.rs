@@ -0,0 +1,577 @@
+pub mod cli;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::compare::MismatchClass;
+use crate::http::parser::Limits;
+
+// Configuration of the proxy and its subcommands. Everything has a default so
+// that running without a config file behaves like the hardcoded setup used in
+// the testbed. A config file is JSON, every field is optional:
+//
+// {
+//     "proxy": "127.0.0.1:1234",
+//     "main": "127.0.0.1:4001",
+//     "shadows": [
+//         { "name": "refactor", "address": "127.0.0.1:4002", "sampling": 0.5,
+//           "template_sampling": { "/users/{id}": 0.05 },
+//           "timeout_ms": 5000, "breaker": { "failures": 5, "cooldown_ms": 30000 },
+//           "compare": { "ignore_headers": ["Date", "ETag"] },
+//           "rewrite": { "set_headers": { "Host": "refactor.internal" },
+//                        "path": [{ "pattern": "^/api/", "replace": "/v2/api/" }],
+//                        "remove_query": ["_"] } }
+//     ],
+//     "store": "results.jsonl",
+//     "tee_limit": 10485760,
+//     "limits": { "request_line": 8192, "header_count": 100, "header_bytes": 65536,
+//                 "body": 104857600 },
+//     "client_timeouts": { "first_byte_ms": 60000, "header_ms": 10000,
+//                          "body_ms": 60000, "write_ms": 30000 },
+//     "shutdown_timeout_ms": 30000,
+//     "samples_per_cluster": 5,
+//     "routes": [
+//         { "name": "users", "method": "GET", "path": "/users/*", "host": "api.example.com",
+//           "main": "127.0.0.1:5001", "shadows": ["refactor"] }
+//     ],
+//     "unmatched": "pass_through",
+//     "templates": ["/repos/{owner}/{repo}"],
+//     "canary": { "shadow": "refactor", "fraction": 0.05,
+//                 "critical": ["status", "body"], "sticky_cookie": "session" },
+//     "mode": "mirror",
+//     "capture": "traffic.cap",
+//     "compare": { "ignore_headers": ["Date"], "hash_content_types": ["image/", "application/pdf"],
+//                  "hash_above": 1048576 },
+//     "replay": { "rate": 50.0, "concurrency": 8, "time_scale": 1.0 },
+//     "logging": { "level": "info", "format": "json", "directory": "logs",
+//                  "rotation": { "max_bytes": 10485760, "every": "daily", "keep": 7 } },
+//     "admin": { "address": "127.0.0.1:9100", "token": "secret" }
+// }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub proxy: String,
+    pub main: String,
+    // Every sampled request is mirrored to each of these, results are stored
+    // per shadow name.
+    pub shadows: Vec<ShadowConfig>,
+    // When empty every request goes to main and is mirrored to all shadows.
+    // Otherwise the first matching route decides where a request goes.
+    pub routes: Vec<RouteConfig>,
+    // What happens to requests that match none of the routes.
+    pub unmatched: Unmatched,
+    // Route templates like /users/{id} that results are aggregated by.
+    // Route paths with parameters are templates too, and numeric, UUID and
+    // hash segments of other paths are replaced automatically.
+    pub templates: Vec<String>,
+    // File that comparison results are appended to.
+    pub store: PathBuf,
+    // Bodies are streamed between the client and main. At most this many
+    // bytes of a message are kept for the shadows and the comparison, larger
+    // responses are compared by length and SHA-256 and larger requests are
+    // not mirrored.
+    pub tee_limit: usize,
+    // Requests from clients over these limits are answered with 414, 431 or
+    // 413 and are not forwarded.
+    pub limits: Limits,
+    pub client_timeouts: ClientTimeoutConfig,
+    // On SIGTERM or Ctrl-C the proxy stops accepting connections and gets
+    // this long to finish the requests in flight and to compare and store
+    // the queued exchanges.
+    pub shutdown_timeout_ms: u64,
+    // Raw exchanges kept in the store per mismatch signature, later
+    // mismatches with the same signature are stored without them.
+    pub samples_per_cluster: usize,
+    pub mode: Mode,
+    // Answer part of the traffic with a shadow's response instead of main's.
+    pub canary: Option<CanaryConfig>,
+    // When set, every request and main response handled by the proxy is
+    // appended to this capture file so it can be replayed later.
+    pub capture: Option<PathBuf>,
+    pub compare: CompareConfig,
+    pub replay: ReplayConfig,
+    pub logging: LoggingConfig,
+    // Listener for metrics and runtime control, not started when not set.
+    pub admin: Option<AdminConfig>,
+    // The file the config was loaded from, used to reload the ignore rules.
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    pub address: String,
+    // When set, requests need an "Authorization: Bearer <token>" header.
+    #[serde(skip_serializing)]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShadowConfig {
+    pub name: String,
+    pub address: String,
+    // Fraction of the proxied requests that is mirrored to this shadow, from
+    // 0.0 to 1.0. Replays always send every request.
+    pub sampling: f64,
+    // Sampling rates for route templates like /users/{id}, used instead of
+    // sampling for the requests with that template.
+    pub template_sampling: BTreeMap<String, f64>,
+    // How long to wait for the full shadow response.
+    pub timeout_ms: u64,
+    pub breaker: BreakerConfig,
+    // Comparison rules for this shadow, the top level rules are used when
+    // not set.
+    pub compare: Option<CompareConfig>,
+    // Changes made to the copy of the request that is sent to this shadow.
+    pub rewrite: RewriteConfig,
+}
+
+// Stops mirroring to a shadow after a number of failed requests in a row.
+// After the cooldown a single request is let through to probe the shadow,
+// the breaker closes again when it succeeds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BreakerConfig {
+    // Failures in a row that open the breaker, 0 disables it.
+    pub failures: u32,
+    pub cooldown_ms: u64,
+}
+
+// Timeouts on client connections, so slow clients can not hold on to the
+// connection handlers. A client that is too slow to send its request gets a
+// 408 Request Timeout.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientTimeoutConfig {
+    // From accepting the connection until the first byte of the request
+    // arrives, the connection is then closed without a response. Connections
+    // carry a single request, there is no keep-alive. Was called idle_ms.
+    #[serde(alias = "idle_ms")]
+    pub first_byte_ms: u64,
+    // For the whole request line and header section, from its first byte.
+    pub header_ms: u64,
+    // For the whole request body, from the end of the header section.
+    pub body_ms: u64,
+    // For every write of the response to the client.
+    pub write_ms: u64,
+}
+
+// Header values can be templates with these placeholders:
+// {shadow}        the name of the shadow
+// {header:Name}   the value of a header in the original request
+// {env:NAME}      an environment variable, read once at startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RewriteConfig {
+    // Headers to add, or replace when the request already has them.
+    pub set_headers: BTreeMap<String, String>,
+    pub remove_headers: Vec<String>,
+    // Regex replacements applied in order to the request target.
+    pub path: Vec<Replacement>,
+    // Query parameters left out of the target, e.g. cache busters that would
+    // make the shadow miss its cache. Applied after the path replacements.
+    pub remove_query: Vec<String>,
+    // Regex replacements applied in order to the body, Content-Length is
+    // updated when the body changes. Chunked bodies are left alone.
+    pub body: Vec<Replacement>,
+    // Header added to every request sent to the shadow so it can tell
+    // mirrored traffic apart. No header is added when set to null.
+    pub marker: Option<Marker>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replacement {
+    pub pattern: String,
+    // Can refer to capture groups with $1 or ${name}.
+    pub replace: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Marker {
+    pub header: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RouteConfig {
+    // Shown in the metrics, the path pattern is used when not set.
+    pub name: Option<String>,
+    // Method name like GET, any method when not set.
+    pub method: Option<String>,
+    // Path prefix, a template when it contains a '{' like /users/{id}, or a
+    // glob when it contains a '*'. A single '*' matches within one path
+    // segment, '**' matches across segments. The query string is not part of
+    // the match. Any path when not set.
+    pub path: Option<String>,
+    // Value of the Host header, the port is ignored when the pattern has
+    // none. Any host when not set.
+    pub host: Option<String>,
+    // Main for this route, the top level main when not set.
+    pub main: Option<String>,
+    // Names of the shadows this route is mirrored to.
+    pub shadows: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Unmatched {
+    // Forward to the top level main without mirroring.
+    PassThrough,
+    // Answer with 404 Not Found.
+    Reject,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CanaryConfig {
+    // Name of the shadow that answers canary requests. Only requests that
+    // are routed to this shadow can become canary requests.
+    pub shadow: String,
+    // Fraction of the requests answered by the shadow, from 0.0 to 1.0.
+    pub fraction: f64,
+    // Kinds of differences with main that make the proxy fall back to main's
+    // response. The proxy always falls back when the shadow fails.
+    pub critical: Vec<MismatchClass>,
+    // Assign clients by the value of this header (or cookie) so the same
+    // client keeps getting answers from the same side. Random otherwise.
+    pub sticky_header: Option<String>,
+    pub sticky_cookie: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    // Forward to main and mirror every request to the shadow.
+    Mirror,
+    // Only forward to main and record the exchanges in the capture file, the
+    // shadow comparison is done later with the compare subcommand.
+    Capture,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompareConfig {
+    // Header names (case insensitive) that are expected to differ between
+    // main and shadow and are therefore not compared.
+    pub ignore_headers: Vec<String>,
+    // Bodies with one of these content types, matched by prefix so "image/"
+    // covers every image, are compared by length and SHA-256 only. So are
+    // bodies larger than hash_above bytes. The bodies of such responses are
+    // not kept in the store.
+    pub hash_content_types: Vec<String>,
+    pub hash_above: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReplayConfig {
+    // Maximum amount of requests per second, unlimited when not set.
+    pub rate: Option<f64>,
+    // Maximum amount of requests in flight at the same time.
+    pub concurrency: usize,
+    // Multiplier for the recorded spacing between requests. 1.0 reproduces
+    // the original timing, 0.5 replays twice as fast. When not set requests
+    // are sent as fast as rate and concurrency allow.
+    pub time_scale: Option<f64>,
+}
+
+// Events about the shadows go to shadow.log, everything else to main.log.
+// Both files are appended to and live in the configured directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    // A level like "debug", or filter directives like "shadowapi=debug,warn".
+    pub level: String,
+    pub format: LogFormat,
+    pub directory: PathBuf,
+    // Also write every event to stdout.
+    pub stdout: bool,
+    pub rotation: RotationConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    Plain,
+    Json,
+}
+
+// A log file is rotated when it grows beyond max_bytes or when a new period
+// starts, whichever comes first. Rotated files get a numbered suffix
+// (main.log.1 is the most recent) and only the newest `keep` are kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RotationConfig {
+    pub max_bytes: Option<u64>,
+    pub every: Option<RotationPeriod>,
+    pub keep: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RotationPeriod {
+    Hourly,
+    Daily,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            proxy: String::from("127.0.0.1:1234"),
+            main: String::from("127.0.0.1:4001"),
+            shadows: vec![ShadowConfig::default()],
+            routes: Vec::new(),
+            unmatched: Unmatched::PassThrough,
+            templates: Vec::new(),
+            store: PathBuf::from("results.jsonl"),
+            tee_limit: 10 * 1024 * 1024,
+            limits: Limits::default(),
+            client_timeouts: ClientTimeoutConfig::default(),
+            shutdown_timeout_ms: 30_000,
+            samples_per_cluster: 5,
+            mode: Mode::Mirror,
+            canary: None,
+            capture: None,
+            compare: CompareConfig::default(),
+            replay: ReplayConfig::default(),
+            logging: LoggingConfig::default(),
+            admin: None,
+            source: None,
+        }
+    }
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        AdminConfig {
+            address: String::from("127.0.0.1:9100"),
+            token: None,
+        }
+    }
+}
+
+impl Default for ClientTimeoutConfig {
+    fn default() -> Self {
+        ClientTimeoutConfig {
+            first_byte_ms: 60_000,
+            header_ms: 10_000,
+            body_ms: 60_000,
+            write_ms: 30_000,
+        }
+    }
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        BreakerConfig {
+            failures: 5,
+            cooldown_ms: 30_000,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: String::from("info"),
+            format: LogFormat::Plain,
+            directory: PathBuf::from("."),
+            stdout: true,
+            rotation: RotationConfig::default(),
+        }
+    }
+}
+
+impl Default for RotationConfig {
+    fn default() -> Self {
+        RotationConfig {
+            max_bytes: None,
+            every: None,
+            keep: 5,
+        }
+    }
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        ShadowConfig {
+            name: String::from("shadow"),
+            address: String::from("127.0.0.1:4002"),
+            sampling: 1.0,
+            template_sampling: BTreeMap::new(),
+            timeout_ms: 10_000,
+            breaker: BreakerConfig::default(),
+            compare: None,
+            rewrite: RewriteConfig::default(),
+        }
+    }
+}
+
+impl ShadowConfig {
+    // The comparison rules that apply to this shadow.
+    pub fn rules<'a>(&'a self, config: &'a Config) -> &'a CompareConfig {
+        self.compare.as_ref().unwrap_or(&config.compare)
+    }
+}
+
+impl RouteConfig {
+    pub fn name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        return self.path.clone().unwrap_or_else(|| String::from("*"));
+    }
+}
+
+impl Default for CanaryConfig {
+    fn default() -> Self {
+        CanaryConfig {
+            shadow: String::new(),
+            fraction: 0.0,
+            critical: vec![MismatchClass::Status],
+            sticky_header: None,
+            sticky_cookie: None,
+        }
+    }
+}
+
+impl Default for RewriteConfig {
+    fn default() -> Self {
+        RewriteConfig {
+            set_headers: BTreeMap::new(),
+            remove_headers: Vec::new(),
+            remove_query: Vec::new(),
+            path: Vec::new(),
+            body: Vec::new(),
+            marker: Some(Marker {
+                header: String::from("X-Shadow-Request"),
+                value: String::from("{shadow}"),
+            }),
+        }
+    }
+}
+
+impl Default for CompareConfig {
+    fn default() -> Self {
+        CompareConfig {
+            ignore_headers: vec![String::from("Date")],
+            hash_content_types: vec![
+                String::from("image/"),
+                String::from("audio/"),
+                String::from("video/"),
+                String::from("application/octet-stream"),
+                String::from("application/pdf"),
+                String::from("application/zip"),
+            ],
+            hash_above: 1024 * 1024,
+        }
+    }
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        ReplayConfig {
+            rate: None,
+            concurrency: 16,
+            time_scale: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load<T>(path: T) -> Result<Config, std::io::Error>
+    where
+        T: AsRef<Path>,
+    {
+        let file = File::open(path.as_ref())?;
+        let mut config: Config = serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid config {}: {}", path.as_ref().display(), e),
+            )
+        })?;
+        config.source = Some(path.as_ref().to_path_buf());
+        Ok(config)
+    }
+
+    // Checks the combinations of settings that can not be expressed in the
+    // types alone.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.shadows.is_empty() && self.mode == Mode::Mirror {
+            return Err(String::from("at least one shadow is needed to mirror traffic"));
+        }
+
+        for (i, shadow) in self.shadows.iter().enumerate() {
+            if self.shadows[..i].iter().any(|s| s.name == shadow.name) {
+                return Err(format!("shadow name {} is used more than once", shadow.name));
+            }
+            if !(0.0..=1.0).contains(&shadow.sampling) {
+                return Err(format!("sampling of shadow {} must be between 0 and 1", shadow.name));
+            }
+            for (template, rate) in shadow.template_sampling.iter() {
+                if !(0.0..=1.0).contains(rate) {
+                    return Err(format!("sampling of {} for shadow {} must be between 0 and 1", template, shadow.name));
+                }
+            }
+        }
+
+        if let Some(canary) = &self.canary {
+            if !self.shadows.iter().any(|s| s.name == canary.shadow) {
+                return Err(format!("canary refers to unknown shadow {}", canary.shadow));
+            }
+            if !(0.0..=1.0).contains(&canary.fraction) {
+                return Err(String::from("canary fraction must be between 0 and 1"));
+            }
+            if canary.sticky_header.is_some() && canary.sticky_cookie.is_some() {
+                return Err(String::from("canary can be sticky by header or by cookie, not both"));
+            }
+        }
+
+        for route in self.routes.iter() {
+            if let Some(name) = route.shadows.iter().find(|n| !self.shadows.iter().any(|s| &s.name == *n)) {
+                return Err(format!("route refers to unknown shadow {}", name));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shadows_from_json() {
+        let config: Config = serde_json::from_str(
+            r#"{"shadows": [{"name": "a", "address": "x:1", "sampling": 0.25}, {"name": "b"}]}"#,
+        )
+        .expect("valid config");
+
+        assert_eq!(config.shadows.len(), 2);
+        assert_eq!(config.shadows[0].sampling, 0.25);
+        assert_eq!(config.shadows[1].address, "127.0.0.1:4002");
+        assert_eq!(config.shadows[1].timeout_ms, 10_000);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn first_byte_timeout_alias() {
+        let config: Config = serde_json::from_str(r#"{"client_timeouts": {"idle_ms": 500}}"#).expect("valid config");
+        assert_eq!(config.client_timeouts.first_byte_ms, 500);
+    }
+
+    #[test]
+    fn duplicate_shadow_names() {
+        let config: Config = serde_json::from_str(r#"{"shadows": [{"name": "a"}, {"name": "a"}]}"#)
+            .expect("valid config");
+        assert!(config.validate().is_err());
+    }
+}
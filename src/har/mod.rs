@@ -0,0 +1,676 @@
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::capture::CaptureRecord;
+use crate::compare::ComparisonRecord;
+use crate::http::parser::MessageParser;
+use crate::http::partials::HttpMethod;
+use crate::http::request::{RawHttpRequest, REQUEST_ID_HEADER};
+use crate::util::base64;
+
+// HAR 1.2 as described in http://www.softwareishard.com/blog/har-12-spec/.
+// Only the fields that are needed to rebuild requests are required when
+// importing, everything else falls back to a default.
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Har {
+    pub log: Log,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Log {
+    #[serde(default = "default_version")]
+    pub version: String,
+    #[serde(default)]
+    pub creator: Creator,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pages: Vec<Page>,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Creator {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Page {
+    pub started_date_time: String,
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub page_timings: PageTimings,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageTimings {}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pageref: Option<String>,
+    pub started_date_time: String,
+    #[serde(default)]
+    pub time: f64,
+    pub request: Request,
+    #[serde(default)]
+    pub response: Response,
+    #[serde(default)]
+    pub cache: Cache,
+    #[serde(default)]
+    pub timings: Timings,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    // Custom field (HAR allows fields starting with an underscore) holding
+    // the correlation ID of the exchange.
+    #[serde(rename = "_requestId", default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    #[serde(default = "default_http_version")]
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<NameValue>,
+    #[serde(default)]
+    pub headers: Vec<NameValue>,
+    #[serde(default)]
+    pub query_string: Vec<NameValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<PostData>,
+    #[serde(default = "unknown_size")]
+    pub headers_size: i64,
+    #[serde(default = "unknown_size")]
+    pub body_size: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Response {
+    pub status: u16,
+    pub status_text: String,
+    pub http_version: String,
+    pub cookies: Vec<NameValue>,
+    pub headers: Vec<NameValue>,
+    pub content: Content,
+    #[serde(rename = "redirectURL")]
+    pub redirect_url: String,
+    pub headers_size: i64,
+    pub body_size: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NameValue {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostData {
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub text: String,
+    // Not part of HAR 1.2 for post data, but used the same way as for the
+    // response content so binary request bodies survive a round trip.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Content {
+    pub size: i64,
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Cache {}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Timings {
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Response {
+            status: 0,
+            status_text: String::new(),
+            http_version: default_http_version(),
+            cookies: Vec::new(),
+            headers: Vec::new(),
+            content: Content::default(),
+            redirect_url: String::new(),
+            headers_size: -1,
+            body_size: -1,
+        }
+    }
+}
+
+fn default_version() -> String {
+    String::from("1.2")
+}
+
+fn default_http_version() -> String {
+    String::from("HTTP/1.1")
+}
+
+fn unknown_size() -> i64 {
+    -1
+}
+
+impl Har {
+    fn new(pages: Vec<Page>, entries: Vec<Entry>) -> Har {
+        Har {
+            log: Log {
+                version: default_version(),
+                creator: Creator {
+                    name: String::from(env!("CARGO_PKG_NAME")),
+                    version: String::from(env!("CARGO_PKG_VERSION")),
+                },
+                pages,
+                entries,
+            },
+        }
+    }
+
+    pub fn write<W>(&self, writer: W) -> Result<(), std::io::Error>
+    where
+        W: Write,
+    {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+}
+
+// Reads a HAR file and turns every entry into a capture record that can be
+// replayed.
+pub fn import<T>(path: T) -> Result<Vec<CaptureRecord>, std::io::Error>
+where
+    T: AsRef<Path>,
+{
+    let har: Har = serde_json::from_reader(BufReader::new(File::open(path)?))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
+    har.log
+        .entries
+        .iter()
+        .map(|entry| {
+            entry_to_record(entry).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        })
+        .collect()
+}
+
+fn entry_to_record(entry: &Entry) -> Result<CaptureRecord, String> {
+    let timestamp = DateTime::parse_from_rfc3339(&entry.started_date_time)
+        .map_err(|e| format!("bad startedDateTime {}: {}", entry.started_date_time, e))?
+        .with_timezone(&Utc);
+
+    let request = &entry.request;
+    let (authority, target) = split_url(&request.url)
+        .ok_or_else(|| format!("bad request url: {}", request.url))?;
+
+    // Browsers record HTTP/2 and HTTP/3 exchanges, these are replayed as
+    // HTTP/1.1 since that is what the proxy speaks.
+    let version = if request.http_version.starts_with("HTTP/1.") {
+        request.http_version.as_str()
+    } else {
+        "HTTP/1.1"
+    };
+
+    let body: Vec<u8> = match &request.post_data {
+        Some(p) if p.encoding.as_deref() == Some("base64") => {
+            base64::decode(&p.text).ok_or_else(|| String::from("post data is not valid base64"))?
+        }
+        Some(p) => p.text.clone().into_bytes(),
+        None => Vec::new(),
+    };
+
+    let mut head = format!("{} {} {}\r\n", request.method, target, version);
+    let mut has_host = false;
+
+    for header in request.headers.iter() {
+        let name = header.name.as_str();
+        // HTTP/2 pseudo headers and framing headers are not copied, framing
+        // is recalculated for the rebuilt body.
+        if name.starts_with(':')
+            || name.eq_ignore_ascii_case("content-length")
+            || name.eq_ignore_ascii_case("transfer-encoding")
+        {
+            continue;
+        }
+        has_host |= name.eq_ignore_ascii_case("host");
+        head.push_str(&format!("{}: {}\r\n", name, header.value));
+    }
+
+    if !has_host {
+        if let Some(authority) = authority {
+            head.push_str(&format!("Host: {}\r\n", authority));
+        }
+    }
+
+    if !body.is_empty() || request.post_data.is_some() {
+        head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    head.push_str("\r\n");
+
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(&body);
+
+    Ok(CaptureRecord {
+        timestamp,
+        request: RawHttpRequest::from(bytes),
+        response: None,
+    })
+}
+
+// Splits an absolute url into its authority and the origin-form target. A
+// url that is already in origin-form has no authority.
+fn split_url(url: &str) -> Option<(Option<&str>, String)> {
+    if url.starts_with('/') {
+        return Some((None, String::from(url)));
+    }
+
+    let rest = &url[url.find("://")? + 3..];
+    match rest.find(['/', '?']) {
+        Some(i) if rest.as_bytes()[i] == b'/' => Some((Some(&rest[..i]), String::from(&rest[i..]))),
+        // http://host?x=1 has an empty path, which means "/".
+        Some(i) => Some((Some(&rest[..i]), format!("/{}", &rest[i..]))),
+        None => Some((Some(rest), String::from("/"))),
+    }
+}
+
+// The parts of a raw HTTP message that HAR cares about.
+struct MessageParts {
+    start_line: String,
+    headers: Vec<NameValue>,
+    // Length of the start line and headers.
+    head: usize,
+    // The body content, without chunk framing.
+    body: Vec<u8>,
+    // The message ends before its body does, e.g. when only the start of it
+    // was kept because it was larger than the tee limit.
+    truncated: bool,
+}
+
+fn split_message(bytes: &[u8], mut parser: MessageParser) -> MessageParts {
+    let mut parts = MessageParts {
+        start_line: String::new(),
+        headers: Vec::new(),
+        head: bytes.len(),
+        body: Vec::new(),
+        truncated: false,
+    };
+
+    let mut cursor = 0;
+    let mut first = true;
+
+    while let Some(lf) = bytes[cursor..].iter().position(|&b| b == b'\n') {
+        let line = String::from_utf8_lossy(&bytes[cursor..cursor + lf]);
+        let line = line.trim_end_matches('\r');
+        cursor = cursor + lf + 1;
+
+        if first {
+            parts.start_line = String::from(line);
+            first = false;
+            continue;
+        }
+
+        if line.is_empty() {
+            parts.head = cursor;
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            parts.headers.push(NameValue {
+                name: String::from(name.trim()),
+                value: String::from(value.trim()),
+            });
+        }
+    }
+
+    if first {
+        parts.start_line = String::from_utf8_lossy(bytes).into_owned();
+    }
+
+    // The parser takes the chunk framing off the body.
+    let complete = parser.scan(bytes).is_ok() && parser.close().is_ok();
+    if complete {
+        parts.body = parser.content().iter().map(|r| &bytes[r.clone()]).collect::<Vec<&[u8]>>().concat();
+    } else {
+        parts.truncated = parts.head < bytes.len();
+    }
+
+    return parts;
+}
+
+fn header_value<'a>(headers: &'a [NameValue], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|h| h.name.eq_ignore_ascii_case(name))
+        .map(|h| h.value.as_str())
+}
+
+// Body text for HAR, bodies that are not UTF-8 are base64 encoded.
+fn body_text(body: &[u8]) -> (String, Option<String>) {
+    match std::str::from_utf8(body) {
+        Ok(text) => (String::from(text), None),
+        Err(_) => (base64::encode(body), Some(String::from("base64"))),
+    }
+}
+
+// The request, and whether its body was left out because it is truncated.
+fn har_request(bytes: &[u8]) -> (Request, bool) {
+    let parts = split_message(bytes, MessageParser::request());
+    let mut start = parts.start_line.splitn(3, ' ');
+    let method = String::from(start.next().unwrap_or_default());
+    let target = start.next().unwrap_or_default();
+    let http_version = String::from(start.next().unwrap_or("HTTP/1.1"));
+
+    let url = if target.starts_with('/') {
+        let host = header_value(&parts.headers, "host").unwrap_or("localhost");
+        format!("http://{}{}", host, target)
+    } else {
+        String::from(target)
+    };
+
+    let query_string = match target.split_once('?') {
+        Some((_, query)) => query
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let (name, value) = p.split_once('=').unwrap_or((p, ""));
+                NameValue {
+                    name: String::from(name),
+                    value: String::from(value),
+                }
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let post_data = if parts.body.is_empty() || parts.truncated {
+        None
+    } else {
+        let (text, encoding) = body_text(&parts.body);
+        Some(PostData {
+            mime_type: String::from(header_value(&parts.headers, "content-type").unwrap_or_default()),
+            text,
+            encoding,
+        })
+    };
+
+    let request = Request {
+        method,
+        url,
+        http_version,
+        cookies: Vec::new(),
+        headers_size: parts.head as i64,
+        body_size: (bytes.len() - parts.head) as i64,
+        headers: parts.headers,
+        query_string,
+        post_data,
+    };
+    return (request, parts.truncated);
+}
+
+// The response to a request with this method, and whether its body was left
+// out because it is truncated.
+fn har_response(bytes: &[u8], method: &HttpMethod) -> (Response, bool) {
+    let parts = split_message(bytes, MessageParser::response(method));
+    let mut start = parts.start_line.splitn(3, ' ');
+    let http_version = String::from(start.next().unwrap_or("HTTP/1.1"));
+    let status: u16 = start.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+    let status_text = String::from(start.next().unwrap_or_default());
+
+    let (text, encoding) = if parts.truncated {
+        (None, None)
+    } else {
+        let (text, encoding) = body_text(&parts.body);
+        (Some(text), encoding)
+    };
+
+    let response = Response {
+        status,
+        status_text,
+        http_version,
+        cookies: Vec::new(),
+        content: Content {
+            size: parts.body.len() as i64,
+            mime_type: String::from(header_value(&parts.headers, "content-type").unwrap_or_default()),
+            text,
+            encoding,
+        },
+        redirect_url: String::from(header_value(&parts.headers, "location").unwrap_or_default()),
+        headers_size: parts.head as i64,
+        body_size: (bytes.len() - parts.head) as i64,
+        headers: parts.headers,
+    };
+    return (response, parts.truncated);
+}
+
+fn entry(
+    timestamp: DateTime<Utc>,
+    request: &[u8],
+    response: Option<&[u8]>,
+    latency_ms: Option<f64>,
+) -> Entry {
+    let time = latency_ms.unwrap_or(0.0);
+    let (request, request_truncated) = har_request(request);
+    let request_id = header_value(&request.headers, REQUEST_ID_HEADER).map(String::from);
+    let method = HttpMethod::try_from(request.method.as_str()).unwrap_or(HttpMethod::Get);
+    let (response, response_truncated) = match response {
+        Some(response) => har_response(response, &method),
+        None => (Response::default(), false),
+    };
+
+    // Truncated bodies are left out, the comment tells why.
+    let comment = match (request_truncated, response_truncated) {
+        (true, true) => Some("request and response bodies truncated"),
+        (true, false) => Some("request body truncated"),
+        (false, true) => Some("response body truncated"),
+        (false, false) => None,
+    };
+
+    Entry {
+        pageref: None,
+        started_date_time: timestamp.to_rfc3339(),
+        time,
+        request,
+        response,
+        cache: Cache {},
+        timings: Timings {
+            send: 0.0,
+            wait: time,
+            receive: 0.0,
+        },
+        comment: comment.map(String::from),
+        request_id,
+    }
+}
+
+// Puts a label in front of the comment of an entry.
+fn label(entry: &mut Entry, label: String) {
+    entry.comment = match entry.comment.take() {
+        Some(comment) => Some(format!("{}, {}", label, comment)),
+        None => Some(label),
+    };
+}
+
+// One entry per captured exchange.
+pub fn from_captures<I>(records: I) -> Result<Har, std::io::Error>
+where
+    I: IntoIterator<Item = Result<CaptureRecord, std::io::Error>>,
+{
+    let mut entries: Vec<Entry> = Vec::new();
+    for record in records {
+        let record = record?;
+        let latency_ms = record
+            .response
+            .as_ref()
+            .and_then(|r| r.latency)
+            .map(|d| d.as_secs_f64() * 1000.0);
+        entries.push(entry(
+            record.timestamp,
+            &record.request.bytes,
+            record.response.as_ref().map(|r| &r.bytes[..]),
+            latency_ms,
+        ));
+    }
+    Ok(Har::new(Vec::new(), entries))
+}
+
+// Every mismatch becomes a page holding two entries, the exchange with main
+// and the same request answered by the shadow, so they show up side by side.
+// The entry comments tell which is which.
+pub fn from_mismatches(records: &[ComparisonRecord]) -> Har {
+    let mut pages: Vec<Page> = Vec::new();
+    let mut entries: Vec<Entry> = Vec::new();
+
+    for record in records.iter() {
+        let exchange = match &record.exchange {
+            Some(e) => e,
+            None => continue,
+        };
+
+        let id = format!("mismatch_{}", pages.len() + 1);
+        pages.push(Page {
+            started_date_time: record.timestamp.to_rfc3339(),
+            id: id.clone(),
+            title: format!("{} {} ({})", record.method, record.target, record.shadow),
+            page_timings: PageTimings {},
+        });
+
+        let mut main = entry(record.timestamp, &exchange.request, Some(&exchange.main), record.main_latency_ms);
+        main.pageref = Some(id.clone());
+        label(&mut main, String::from("main"));
+        entries.push(main);
+
+        let mut shadow = entry(record.timestamp, &exchange.request, Some(&exchange.shadow), record.shadow_latency_ms);
+        shadow.pageref = Some(id);
+        label(&mut shadow, format!("shadow {}", record.shadow));
+        entries.push(shadow);
+    }
+
+    Har::new(pages, entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn import_entry() {
+        let har = r#"{"log": {"entries": [{
+            "startedDateTime": "2024-05-01T10:00:00.000Z",
+            "request": {
+                "method": "POST",
+                "url": "https://example.com/json?debug=1",
+                "httpVersion": "h2",
+                "headers": [
+                    {"name": ":authority", "value": "example.com"},
+                    {"name": "content-length", "value": "999"},
+                    {"name": "accept", "value": "*/*"}
+                ],
+                "postData": {"mimeType": "application/json", "text": "{}"}
+            }
+        }]}}"#;
+        let har: Har = serde_json::from_str(har).expect("valid har");
+        let record = entry_to_record(&har.log.entries[0]).expect("convertible entry");
+
+        assert_eq!(
+            String::from_utf8(record.request.bytes.to_vec()).unwrap(),
+            "POST /json?debug=1 HTTP/1.1\r\naccept: */*\r\nHost: example.com\r\nContent-Length: 2\r\n\r\n{}"
+        );
+        assert_eq!(record.timestamp.to_rfc3339(), "2024-05-01T10:00:00+00:00");
+    }
+
+    #[test]
+    fn split_urls() {
+        assert_eq!(split_url("http://a:80/x?y"), Some((Some("a:80"), String::from("/x?y"))));
+        assert_eq!(split_url("http://a?y=1"), Some((Some("a"), String::from("/?y=1"))));
+        assert_eq!(split_url("http://a"), Some((Some("a"), String::from("/"))));
+        assert_eq!(split_url("/x"), Some((None, String::from("/x"))));
+        assert_eq!(split_url("nonsense"), None);
+    }
+
+    #[test]
+    fn export_capture() {
+        let record = CaptureRecord {
+            timestamp: Utc::now(),
+            request: RawHttpRequest::from(Vec::from("GET /api?a=1&b HTTP/1.1\r\nHost: proxy:1234\r\n\r\n")),
+            response: Some(crate::http::response::RawHttpResponse::from(Vec::from(
+                "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\n\r\nnope",
+            ))),
+        };
+        let har = from_captures(vec![Ok(record)]).expect("exportable");
+        let entry = &har.log.entries[0];
+
+        assert_eq!(entry.request.url, "http://proxy:1234/api?a=1&b");
+        assert_eq!(entry.request.query_string.len(), 2);
+        assert_eq!(entry.response.status, 404);
+        assert_eq!(entry.response.status_text, "Not Found");
+        assert_eq!(entry.response.content.text.as_deref(), Some("nope"));
+        assert_eq!(entry.response.content.mime_type, "text/plain");
+    }
+
+    #[test]
+    fn chunked_round_trip() {
+        let record = CaptureRecord {
+            timestamp: Utc::now(),
+            request: RawHttpRequest::from(Vec::from(
+                "POST /x HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nnope\r\n0\r\n\r\n",
+            )),
+            response: Some(crate::http::response::RawHttpResponse::from(Vec::from(
+                "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n",
+            ))),
+        };
+        let har = from_captures(vec![Ok(record)]).expect("exportable");
+        let entry = &har.log.entries[0];
+        assert_eq!(entry.request.post_data.as_ref().map(|p| p.text.as_str()), Some("nope"));
+        assert_eq!(entry.response.content.text.as_deref(), Some("ok"));
+        assert_eq!(entry.comment, None);
+
+        let replayed = entry_to_record(entry).expect("convertible entry");
+        assert_eq!(
+            String::from_utf8(replayed.request.bytes.to_vec()).unwrap(),
+            "POST /x HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\n\r\nnope"
+        );
+    }
+
+    #[test]
+    fn truncated_bodies_left_out() {
+        let record = CaptureRecord {
+            timestamp: Utc::now(),
+            request: RawHttpRequest::from(Vec::from("POST /x HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc")),
+            response: Some(crate::http::response::RawHttpResponse::from(Vec::from(
+                "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+            ))),
+        };
+        let har = from_captures(vec![Ok(record)]).expect("exportable");
+        let entry = &har.log.entries[0];
+        assert!(entry.request.post_data.is_none());
+        assert_eq!(entry.response.content.text.as_deref(), Some("ok"));
+        assert_eq!(entry.comment.as_deref(), Some("request body truncated"));
+    }
+}
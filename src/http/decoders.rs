@@ -1,432 +1,190 @@
+use crate::http::error::HeaderError;
+use crate::http::field_hash;
+use crate::http::partials::{
+    is_tchar, HeaderField, HttpHeader, HttpHeaderPair, FIELD_BITS, FIELD_DISPLACEMENTS, FIELD_NAMES, FIELD_SLOTS, HEADERS,
+};
 
-// Builds a header pair for the given inputs. Whitespace is trimmed on both ends of the buffer.
-// Note that there are no checks whether these values actually make sense, this should be done
-// before calling this function.
+// Decodes one header line into an owned pair, see decode_field.
+pub fn decode_header(buf: &[u8], s: usize, e: usize) -> Result<HttpHeaderPair, HeaderError> {
+    let field = decode_field(buf, s, e)?;
+    let value = field.text(buf).into_owned();
+    return Ok((field.header, value));
+}
+
+// Decodes one header line without copying the value. Never panics: the line
+// is checked before anything is read from it and every problem is reported
+// as a HeaderError.
 //
-// buf: a reference to a byte slice
-// l: the left side of the value to be parsed
-// r: the right side of the value to be parsed
-// h: the name of the header
-use crate::http::partials::HttpHeader;
-use crate::http::partials::HttpHeaderPair;
+// buf: ref to the raw bytes
+// s: start of the current header line
+// e: end of the current header line (points to the \n)
+pub fn decode_field(buf: &[u8], s: usize, e: usize) -> Result<HeaderField, HeaderError> {
+    if s > e || e > buf.len() {
+        return Err(HeaderError::OutOfBounds { start: s, end: e, len: buf.len() });
+    }
 
-pub fn build_header_pair(buf: &[u8], l: usize, r: usize, h: HttpHeader) -> Option<HttpHeaderPair> {
-    let mut start: usize = l;
-    let mut end: usize = r;
+    // NOTE: in http there are CRLF line endings which mean there is a \r
+    // before \n at position [e - 1].
+    let line = &buf[s..e];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
 
-    for p in l..=r {
-        if (buf[p] as char).is_whitespace() {
-            start += 1;
-        } else { break; }
+    if line.is_empty() {
+        return Err(HeaderError::EmptyLine);
+    }
+    if line[0] == b' ' || line[0] == b'\t' {
+        return Err(HeaderError::ObsoleteFolding);
     }
 
-    for p in r..=l {
-        if (buf[p] as char).is_whitespace() {
-            end -= 1;
-        } else { break; }
+    let colon = match line.iter().position(|&b| b == b':') {
+        Some(colon) => colon,
+        None => return Err(HeaderError::MissingColon),
+    };
+    let name = &line[..colon];
+    if name.is_empty() {
+        return Err(HeaderError::EmptyName);
+    }
+    if let Some(at) = name.iter().position(|&b| !is_tchar(b)) {
+        if name[at] == b' ' || name[at] == b'\t' {
+            return Err(HeaderError::WhitespaceBeforeColon(at));
+        }
+        return Err(HeaderError::InvalidName { at, byte: name[at] });
     }
 
-    if start >= end {
-        return None;
+    // field-value = *( VCHAR / obs-text / SP / HTAB ), so only control
+    // characters other than HTAB are refused.
+    let value = &line[colon + 1..];
+    if let Some(i) = value.iter().position(|&b| (b < 0x20 && b != b'\t') || b == 0x7F) {
+        return Err(HeaderError::InvalidValue { at: colon + 1 + i, byte: value[i] });
     }
+    let start = s + colon + 1 + (value.len() - value.trim_ascii_start().len());
+    let end = start + value.trim_ascii().len();
+
+    let header = match recognize(name) {
+        Some(header) => header,
+        None => return Err(HeaderError::UnknownName(String::from_utf8_lossy(name).into_owned())),
+    };
 
-    return Some((h, String::from_utf8(buf[start..end].to_vec()).unwrap()));
+    return Ok(HeaderField { header, value: start..end });
 }
 
-// Checks if the length of a header is correctly set based on the expected offset and the position
-// of the ':' character. If it is lower than the end of the header, it means that the ':' character
-// was not at the expected offset as well. This check is needed since the buffer can exceed the
-// length of one header as it is a byte slice ref.
-//
-// buf: reference to the byte slice
-// s: the start position of the current header
-// e: the end position of the current header (the next \n char)
-// offset: the offset that the ':' character should be expected (and checked)
-pub fn len_match(buf: &[u8], s: usize, e: usize, offset: usize) -> bool {
-    if s+offset < e {
-        return false;
+// Decodes the header section that starts at `start`, up to and including the
+// empty line. Returns the known headers and where the body starts, which is
+// the end of buf when the section is not terminated. Unknown and malformed
+// headers are left out.
+pub fn decode_fields(buf: &[u8], start: usize) -> (Vec<HeaderField>, usize) {
+    let mut fields: Vec<HeaderField> = Vec::with_capacity(10);
+    let mut cursor = start;
+
+    while let Some(lf) = buf.get(cursor..).and_then(|rest| rest.iter().position(|&byte| byte == 0x0A)) {
+        // An empty line (only CRLF or LF) ends the header section.
+        if lf == 0 || (lf == 1 && buf[cursor] == 0x0D) {
+            return (fields, cursor + lf + 1);
+        }
+        if let Ok(field) = decode_field(buf, cursor, cursor + lf) {
+            fields.push(field);
+        }
+        cursor = cursor + lf + 1;
     }
-    return buf[s+offset] == b':';
+
+    return (fields, buf.len());
 }
 
-// buf: ref to the raw bytes
-// s: start of the current header line
-// e: end of the current header line (points to the \n)
-pub fn decode_header(buf: &[u8], s: usize, e: usize) -> Option<HttpHeaderPair> {
-    // NOTE: end is including the position of \n, in http there are CRLF line
-    // endings which mean there is a \r before \n at position [end - 1].
+// Finds the header for a field name, ignoring case. The name should be a
+// token, which decode_header checks first. The table is a perfect hash
+// generated by build.rs: a known name always lands in its own slot, so one
+// comparison tells whether the name is known.
+pub fn recognize(name: &[u8]) -> Option<HttpHeader> {
+    let h = field_hash::hash(name);
+    let displacement = FIELD_DISPLACEMENTS[field_hash::bucket(h, FIELD_DISPLACEMENTS.len())];
+    let index = FIELD_SLOTS[field_hash::slot(h, displacement, FIELD_BITS)] as usize;
 
-    // TODO: byte array access can panic because length is not checked.
+    let expected = FIELD_NAMES.get(index)?;
+    if !field_hash::eq_folded(name, expected.as_bytes()) {
+        return None;
+    }
+    return Some(HEADERS[index].clone());
+}
 
-    //println!("==> {:?}", build_header_pair(buf, s, e, HttpHeader::Tk));
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
 
-    match buf[s] {
-        b'A' => { /* A */
-            match buf[s + 1] {
-                b'c' => { /* Ac */
-                    match buf[s + 4] {
-                        b'p' => { /* Ac[ce]p */
-                            if len_match(buf,s,e,6) {
-                                return build_header_pair(buf, s+7, e-1, HttpHeader::Accept);
-                            }
-                            match buf[s + 7] {
-                                b'P' => { /* Ac[ce]p[t-]P */
-                                    if len_match(buf,s,e,12) {
-                                        return build_header_pair(buf, s+8, e-1, HttpHeader::AcceptPatch);
-                                    }
-                                }
-                                b'R' => { /* Ac[ce]p[t-]R */
-                                    if len_match(buf,s,e,12) {
-                                        return build_header_pair(buf, s+8, e-1, HttpHeader::AcceptRanges);
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                        b's' => { /* Ac[ce]s */
-                            match buf[s+15] {
-                                b'A' => { /* Access-Control-A */
-                                    match buf[s+22] {
-                                        b'O' => { /* Access-Control-Allow-O */
-                                            if len_match(buf,s,e,27) {
-                                                return build_header_pair(buf, s+28, e-1, HttpHeader::AccessControlAllowOrigin);
-                                            }
-                                        }
-                                        b'C' => {
-                                            if len_match(buf,s,e,32) {
-                                                return build_header_pair(buf, s+33, e-1, HttpHeader::AccessControlAllowCredentials);
-                                            }
-                                        }
-                                        b'M' => {
-                                            if len_match(buf,s,e,28) {
-                                                return build_header_pair(buf, s+29, e-1, HttpHeader::AccessControlAllowMethods);
-                                            }
-                                        }
-                                        b'H' => {
-                                            if len_match(buf,s,e,28) {
-                                                return build_header_pair(buf, s+29, e-1, HttpHeader::AccessControlAllowHeaders);
-                                            }
-                                        }
-                                        _ => {}
-                                    }
-                                }
-                                b'E' => {
-                                    if len_match(buf,s,e,29) {
-                                        return build_header_pair(buf, s+30, e-1, HttpHeader::AccessControlExposeHeaders);
-                                    }
-                                }
-                                b'M' => {
-                                    if len_match(buf,s,e,22) {
-                                        return build_header_pair(buf, s+23, e-1, HttpHeader::AccessControlMaxAge);
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                b'g' => { /* Ag */
-                    if len_match(buf, s, e, 3) {
-                        return build_header_pair(buf, s + 4, e - 1, HttpHeader::Age);
-                    }
-                }
-                b'l' => { /* Al */
-                    match buf[s+2] {
-                        b'l' => { /* All */
-                            if len_match(buf,s,e,5) {
-                                return build_header_pair(buf, s+6, e-1, HttpHeader::Allow);
-                            }
-                        }
-                        b't' => { /* Alt */
-                            if len_match(buf,s,e,7) {
-                                return build_header_pair(buf, s+8, e-1, HttpHeader::AltSvc);
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                _ => {}
-            }
-        }
-        b'C' => { /* C */
-            match buf[s + 1] {
-                b'a' => { /* Ca */
-                    if len_match(buf,s,e,13) {
-                        return build_header_pair(buf, s+14, e-1, HttpHeader::CacheControl);
-                    }
-                }
-                b'o' => { /* Co */
-                    match buf[s + 3] {
-                        b'n' => { /* Con */
-                            if len_match(buf,s,e,10) { // TODO: deal with open conns
-                                return build_header_pair(buf, s+11, e-1, HttpHeader::Connection);
-                            }
-                        }
-                        b't' => { /* Cont */
-                            match buf[s + 8] {
-                                b'D' => { /* Content-D */
-                                    if len_match(buf,s,e,19) {
-                                        return build_header_pair(buf, s+20, e-1, HttpHeader::ContentDisposition);
-                                    }
-                                }
-                                b'E' => { /* Content-E */
-                                    if len_match(buf,s,e,16) {
-                                        return build_header_pair(buf, s+17, e-1, HttpHeader::ContentEncoding);
-                                    }
-                                }
-                                b'L' => { /* Content-L */
-                                    match buf[s+ 9] {
-                                        b'a' => { /* Content-La */
-                                            if len_match(buf,s,e,16) {
-                                                return build_header_pair(buf, s+17, e-1, HttpHeader::ContentLanguage);
-                                            }
-                                        }
-                                        b'e' => { /* Content-Le */
-                                            if len_match(buf,s,e,14) {
-                                                return build_header_pair(buf, s + 15, e - 1, HttpHeader::ContentLength);
-                                            }
-                                        }
-                                        b'o' => { /* Content-Lo */
-                                            if len_match(buf,s,e,16) {
-                                                return build_header_pair(buf, s+17, e-1, HttpHeader::ContentLocation);
-                                            }
-                                        }
-                                        _ => {}
-                                    }
-                                }
-                                b'R' => { /* Content-R */
-                                    if len_match(buf,s,e,13) {
-                                        return build_header_pair(buf, s+14, e-1, HttpHeader::ContentRange);
-                                    }
-                                }
-                                b'T' => { /* Content-T */
-                                    if len_match(buf,s,e,12) {
-                                        return build_header_pair(buf, s+13, e-1, HttpHeader::ContentType);
-                                    }
-                                }
-                                b'S' => { /* Content-S */
-                                    if len_match(buf,s,e,23) {
-                                        return build_header_pair(buf, s+24, e-1, HttpHeader::ContentSecurityPolicy);
-                                    }
-                                }
-                                _ => {
-                                }
-                            }
-                        }
-                        _ => {
-                        }
-                    }
-                }
-                _ => {
-                }
-            }
-        }
-        b'D' => { /* D */
-            match buf[s+1] {
-                b'a' => { /* Da */
-                    if len_match(buf,s,e,4) {
-                        return build_header_pair(buf, s+5, e-1, HttpHeader::Date);
-                    }
-                }
-                b'e' => { /* De */
-                    if len_match(buf,s,e,10) {
-                        return build_header_pair(buf, s+11, e-1, HttpHeader::DeltaBase);
-                    }
-                }
-                _ => {}
-            }
-        }
-        b'E' => { /* E */
-            match buf[s+1] {
-                b'T' => { /* ET */
-                    if len_match(buf,s,e,4) {
-                        return build_header_pair(buf, s+5, e-1, HttpHeader::ETag);
-                    }
-                }
-                b'x' => { /* Ex */
-                    if len_match(buf,s,e,7) {
-                        return build_header_pair(buf, s+8, e-1, HttpHeader::Expires);
-                    }
-                }
-                _ => {}
-            }
-        }
-        b'I' => { /* I */
-            if len_match(buf,s,e,2) {
-                return build_header_pair(buf, s+3, e-1, HttpHeader::IM);
-            }
-        }
-        b'L' => { /* L */
-            match buf[s+1] {
-                b'a' => { /* La */
-                    if len_match(buf,s,e,13) {
-                        return build_header_pair(buf, s+14, e-1, HttpHeader::LastModified);
-                    }
-                }
-                b'i' => { /* Li */
-                    if len_match(buf,s,e,4) {
-                        return build_header_pair(buf, s+5, e-1, HttpHeader::Link);
-                    }
-                }
-                b'o' => {
-                    if len_match(buf,s,e,8) {
-                        return build_header_pair(buf, s+9, e-1, HttpHeader::Location);
-                    }
-                }
-                _ => {}
-            }
-        }
-        b'P' => { /* P */
-            match (buf[s+1], buf[s+2]) {
-                (b'r', b'a') => { /* Pra */
-                    if len_match(buf,s,e,6) {
-                        return build_header_pair(buf, s+7, e-1, HttpHeader::Pragma);
-                    }
-                }
-                (b'r', b'o') => { /* Pro */
-                    if len_match(buf,s,e,18) {
-                        return build_header_pair(buf, s+19, e-1, HttpHeader::ProxyAuthenticate);
-                    }
-                }
-                _ => {}
-            }
-            match buf[s+1] {
-                b'u' => {
-                    if len_match(buf,s,e,15) {
-                        return build_header_pair(buf, s+16, e-1, HttpHeader::PublicKeyPins);
-                    }
-                }
-                _ => {}
-            }
-        }
-        b'R' => { /* R */
-            match buf[s+2] {
-                b't' => { /* Ret */
-                    if len_match(buf,s,e,11) {
-                        return build_header_pair(buf, s+12, e-1, HttpHeader::RetryAfter);
-                    }
-                }
-                b'f' => { /* Ref */
-                    if len_match(buf,s,e,7) {
-                        return build_header_pair(buf, s+8, e-1, HttpHeader::Refresh);
-                    }
-                }
-                _ => {}
-            }
-        }
-        b'S' => { /* S */
-            match buf[s+1] {
-                b'e' => { /* Se */
-                    match buf[s+2] {
-                        b'r' => { /* Ser */
-                            if len_match(buf,s,e,6) {
-                                return build_header_pair(buf, s+7, e-1, HttpHeader::Server);
-                            }
-                        }
-                        b't' => { /* Set */
-                            if len_match(buf,s,e,10) {
-                                return build_header_pair(buf, s+11, e-1, HttpHeader::SetCookie);
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                b't' => { /* St */
-                    if len_match(buf,s,e,25) {
-                        return build_header_pair(buf, s+26, e-1, HttpHeader::StrictTransportSecurity);
-                    }
-                }
-                _ => {}
-            }
-        }
-        b'T' => { /* T */
-            match buf[s+1] {
-                b'r' => { /* Tr */
-                    match buf[s+3] {
-                        b'i' => { /* Trai */
-                            if len_match(buf,s,e,7) {
-                                return build_header_pair(buf, s+8, e-1, HttpHeader::Trailer);
-                            }
-                        }
-                        b'n' => { /* Tran */
-                            if len_match(buf,s,e,17) {
-                                return build_header_pair(buf, s+18, e-1, HttpHeader::TransferEncoding);
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-                b'k' => { /* Tk */
-                    if len_match(buf,s,e,2) {
-                        return build_header_pair(buf, s+3, e-1, HttpHeader::Tk);
-                    }
-                }
-                _ => {}
-            }
-        }
-        b'U' => { /* U */
-            if len_match(buf,s,e,7) {
-                return build_header_pair(buf, s+8, e-1, HttpHeader::Upgrade);
-            }
+    fn decode(line: &str) -> Result<HttpHeaderPair, HeaderError> {
+        decode_header(line.as_bytes(), 0, line.len())
+    }
+
+    #[test]
+    fn known_headers() {
+        assert_eq!(decode("Content-Length: 12\r"), Ok((HttpHeader::ContentLength, String::from("12"))));
+        assert_eq!(decode("content-length:12"), Ok((HttpHeader::ContentLength, String::from("12"))));
+        assert_eq!(decode("ETAG: \t\"x\" \r"), Ok((HttpHeader::ETag, String::from("\"x\""))));
+        assert_eq!(decode("X-Request-ID:"), Ok((HttpHeader::XRequestID, String::new())));
+    }
+
+    #[test]
+    fn precise_errors() {
+        assert_eq!(decode("Ac: x"), Err(HeaderError::UnknownName(String::from("Ac"))));
+        assert_eq!(decode("Acceps: x"), Err(HeaderError::UnknownName(String::from("Acceps"))));
+        assert_eq!(decode("A"), Err(HeaderError::MissingColon));
+        assert_eq!(decode("\r"), Err(HeaderError::EmptyLine));
+        assert_eq!(decode(": x"), Err(HeaderError::EmptyName));
+        assert_eq!(decode(" Age: 1"), Err(HeaderError::ObsoleteFolding));
+        assert_eq!(decode("Age : 1"), Err(HeaderError::WhitespaceBeforeColon(3)));
+        assert_eq!(decode("A(e: 1"), Err(HeaderError::InvalidName { at: 1, byte: b'(' }));
+        assert_eq!(decode("Age: 1\0"), Err(HeaderError::InvalidValue { at: 6, byte: 0 }));
+        assert_eq!(
+            decode_header(b"Age: 1", 2, 10),
+            Err(HeaderError::OutOfBounds { start: 2, end: 10, len: 6 })
+        );
+        assert!(decode_header(b"Age: 1", 4, 2).is_err());
+    }
+
+    #[test]
+    fn header_section() {
+        let buf = b"GET / HTTP/1.1\r\nHost:  a \r\nX-Custom: b\r\nAge: 1\n\r\nbody";
+        let (fields, body) = decode_fields(buf, 16);
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0], HeaderField { header: HttpHeader::Host, value: 23..24 });
+        assert_eq!(fields[1].text(buf), "1");
+        assert_eq!(&buf[body..], b"body");
+
+        let (fields, body) = decode_fields(b"Age: 1\r\n", 0);
+        assert_eq!((fields.len(), body), (1, 8));
+    }
+
+    #[test]
+    fn every_known_header() {
+        assert!(HEADERS.len() > 200);
+        for header in HEADERS.iter() {
+            let name: &str = header.into();
+            assert_eq!(decode(&format!("{}: x", name)), Ok((header.clone(), String::from("x"))));
         }
-        b'V' => { /* V */
-            match buf[s+1] {
-                b'a' => { /* Va */
-                    if len_match(buf,s,e,4) {
-                        return build_header_pair(buf, s+5, e-1, HttpHeader::Vary);
-                    }
-                }
-                b'i' => { /* Vi */
-                    if len_match(buf,s,e,3) {
-                        return build_header_pair(buf, s+4, e-1, HttpHeader::Via);
-                    }
-                }
-                _ => {}
-            }
+        assert_eq!(recognize(b"sec-websocket-key"), Some(HttpHeader::SecWebSocketKey));
+        assert_eq!(recognize(b"x-custom"), None);
+        assert_eq!(recognize(&[b'a'; 100]), None);
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics(buf in proptest::collection::vec(any::<u8>(), 0..64), s in 0usize..80, e in 0usize..80) {
+            let _ = decode_header(&buf, s, e);
         }
-        b'W' => { /* W */
-            match buf[s+1] {
-                b'a' => { /* Wa */
-                    if len_match(buf,s,e,7) {
-                        return build_header_pair(buf, s+8, e-1, HttpHeader::Warning);
-                    }
-                }
-                b'W' => { /* WW */
-                    if len_match(buf,s,e,16) {
-                        return build_header_pair(buf, s+17, e-1, HttpHeader::WWWAuthenticate);
-                    }
-                }
-                _ => {}
-            }
+
+        #[test]
+        fn never_panics_on_header_like_lines(name in "[A-Za-z-]{0,40}", value in "[ -~\t]{0,20}") {
+            let line = format!("{}:{}", name, value);
+            let _ = decode(&line);
         }
-        b'X' => { /* X */
-            match buf[s+2] {
-                b'P' => { /* X-P */
-                    if len_match(buf,s,e,12) {
-                        return build_header_pair(buf, s+13, e-1, HttpHeader::XPoweredBy);
-                    }
-                }
-                b'R' => { /* X-R */
-                    if len_match(buf,s,e,12) {
-                        return build_header_pair(buf, s+13, e-1, HttpHeader::XRequestID);
-                    }
-                }
-                b'U' => { /* X-U */
-                    if len_match(buf,s,e,15) {
-                        return build_header_pair(buf, s+16, e-1, HttpHeader::XUACompatible);
-                    }
-                }
-                b'X' => { /* X-X */
-                    if len_match(buf,s,e,16) {
-                        return build_header_pair(buf, s+17, e-1, HttpHeader::XXSSProtection);
-                    }
-                }
-                _ => {}
-            }
+
+        #[test]
+        fn decodes_what_is_written(index in 0..HEADERS.len(), value in "[!-~]([ -~]{0,30}[!-~])?", upper in any::<bool>()) {
+            let header = &HEADERS[index];
+            let name: &str = header.into();
+            let name = if upper { name.to_ascii_uppercase() } else { name.to_ascii_lowercase() };
+            let line = format!("{}: {} \r\nNext: x", name, value);
+            let end = line.find('\n').unwrap();
+            prop_assert_eq!(decode_header(line.as_bytes(), 0, end), Ok((header.clone(), value)));
         }
-        _ => {}
     }
-
-    return None;
 }
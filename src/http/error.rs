@@ -17,7 +17,15 @@ impl Display for ProxyError {
 #[derive(Debug, Clone, Copy)]
 pub enum HttpError {
     BadFormat,
+    BadTarget,
+    BadFraming,
+    Incomplete,
     UnknownVersion,
+    RequestLineTooLong,
+    TooManyHeaders,
+    HeadersTooLarge,
+    BodyTooLarge,
+    Timeout,
 }
 
 impl std::error::Error for HttpError {}
@@ -27,6 +35,49 @@ impl Display for HttpError {
         match self {
             HttpError::UnknownVersion => write!(f, "Http version seems to be unknown"),
             HttpError::BadFormat => write!(f, "Http seems to be wrongly formatted"),
+            HttpError::BadTarget => write!(f, "Request target seems to be wrongly formatted"),
+            HttpError::BadFraming => write!(f, "Message length or chunked encoding seems to be wrong"),
+            HttpError::Incomplete => write!(f, "Message ended before it was complete"),
+            HttpError::RequestLineTooLong => write!(f, "Request line is longer than allowed"),
+            HttpError::TooManyHeaders => write!(f, "Message has more headers than allowed"),
+            HttpError::HeadersTooLarge => write!(f, "Header section is larger than allowed"),
+            HttpError::BodyTooLarge => write!(f, "Body is larger than allowed"),
+            HttpError::Timeout => write!(f, "Message was not received in time"),
+        }
+    }
+}
+
+// Why a header line could not be decoded. Offsets are from the start of the
+// line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderError {
+    OutOfBounds { start: usize, end: usize, len: usize },
+    EmptyLine,
+    ObsoleteFolding,
+    MissingColon,
+    EmptyName,
+    WhitespaceBeforeColon(usize),
+    InvalidName { at: usize, byte: u8 },
+    InvalidValue { at: usize, byte: u8 },
+    UnknownName(String),
+}
+
+impl std::error::Error for HeaderError {}
+
+impl Display for HeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HeaderError::OutOfBounds { start, end, len } => {
+                write!(f, "header line {start}..{end} is outside of the {len} bytes")
+            }
+            HeaderError::EmptyLine => write!(f, "header line is empty"),
+            HeaderError::ObsoleteFolding => write!(f, "header line is folded onto the previous one"),
+            HeaderError::MissingColon => write!(f, "header line has no ':'"),
+            HeaderError::EmptyName => write!(f, "header name is empty"),
+            HeaderError::WhitespaceBeforeColon(at) => write!(f, "whitespace in header name at {at}"),
+            HeaderError::InvalidName { at, byte } => write!(f, "byte 0x{byte:02x} at {at} is not allowed in a header name"),
+            HeaderError::InvalidValue { at, byte } => write!(f, "byte 0x{byte:02x} at {at} is not allowed in a header value"),
+            HeaderError::UnknownName(name) => write!(f, "unknown header {name}"),
         }
     }
 }
@@ -36,6 +87,7 @@ pub enum ServerError {
     Unresponsive(String, Box<dyn Error + Send + Sync>),
     ServerWriteError(String, Box<dyn Error + Send + Sync>),
     ServerReadError(String, Box<dyn Error + Send + Sync>),
+    Timeout(String, std::time::Duration),
 }
 impl std::fmt::Display for ServerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -49,6 +101,9 @@ impl std::fmt::Display for ServerError {
             Self::Unresponsive(target, err) => {
                 write!(f, "server [{target}] is unresponsive, reason: {err}")
             }
+            Self::Timeout(target, after) => {
+                write!(f, "server [{target}] did not respond within {}ms", after.as_millis())
+            }
         }
     }
 }
@@ -0,0 +1,57 @@
+// Hashing for the header field lookup table. build.rs uses the same
+// functions to find the displacements, so changing them changes the table.
+
+// Mixes the length with the first and last eight bytes of the name, so the
+// cost does not depend on the length. ASCII case is folded so the name does
+// not have to be lowercased before the lookup. Other bytes can collide after
+// folding, which is fine since the name is compared after. Names that still
+// collide make build.rs fail.
+pub fn hash(name: &[u8]) -> u64 {
+    let n = name.len();
+    let (head, tail) = match (name.first_chunk::<8>(), name.last_chunk::<8>()) {
+        (Some(head), Some(tail)) => (u64::from_le_bytes(*head), u64::from_le_bytes(*tail)),
+        _ => {
+            let short = name.iter().rev().fold(0u64, |w, &b| w << 8 | b as u64);
+            (short, short)
+        }
+    };
+    let (head, tail) = (head | FOLD, tail | FOLD);
+
+    let mut h = (head ^ tail.rotate_left(32)).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ n as u64;
+    h = (h ^ (h >> 29)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    return h ^ (h >> 32);
+}
+
+// Whether a token equals a known field name, ignoring case. Compares eight
+// bytes at a time with the case bit set. That is exact because field names
+// only have letters, digits and dashes: for a digit or a dash the byte with
+// the case bit flipped is a control character, which a token cannot have.
+pub fn eq_folded(token: &[u8], field: &[u8]) -> bool {
+    if token.len() != field.len() {
+        return false;
+    }
+    let mut a = token.chunks_exact(8);
+    let mut b = field.chunks_exact(8);
+    for (x, y) in a.by_ref().zip(b.by_ref()) {
+        let x = u64::from_le_bytes(x.try_into().unwrap_or_default());
+        let y = u64::from_le_bytes(y.try_into().unwrap_or_default());
+        if x | FOLD != y | FOLD {
+            return false;
+        }
+    }
+    return a.remainder().iter().zip(b.remainder()).all(|(x, y)| x | 0x20 == y | 0x20);
+}
+
+// The case bit of every byte in a word.
+const FOLD: u64 = 0x2020_2020_2020_2020;
+
+// The bucket of a hash, each bucket has its own displacement.
+pub fn bucket(h: u64, buckets: usize) -> usize {
+    return (h >> 32) as usize % buckets;
+}
+
+// The slot of a hash in a table of 2^bits entries.
+pub fn slot(h: u64, displacement: u64, bits: u32) -> usize {
+    let mixed = (h ^ displacement).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    return (mixed >> (64 - bits)) as usize;
+}
@@ -1,5 +1,9 @@
 pub mod error;
+pub mod field_hash;
+pub mod parser;
 pub mod partials;
 pub mod request;
 pub mod response;
+pub mod target;
+pub mod tee;
 pub mod decoders;
@@ -0,0 +1,675 @@
+use std::ops::Range;
+
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+
+use crate::http::decoders::decode_field;
+use crate::http::error::{HeaderError, HttpError};
+use crate::http::partials::{HeaderField, HttpHeader, HttpMethod, HttpStatusCode};
+
+/* How the end of a message is found is described here:
+ * https://httpwg.org/specs/rfc9112.html#message.body.length
+ */
+
+// Longest chunk-size line, extensions included, that is accepted whatever the
+// limits are.
+const MAX_CHUNK_LINE: usize = 1024;
+
+// Where the parser is in the message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseState {
+    // Waiting for the end of the request (or status) line.
+    RequestLine,
+    // Waiting for the empty line that ends the header section.
+    Headers,
+    // This many body bytes are still expected.
+    Body(usize),
+    // Reading a chunked body.
+    Chunked,
+    // A response without a length, it ends when the upstream closes.
+    UntilClose,
+    // The message is complete.
+    Done,
+}
+
+// Limits on the size of a message, a message over one of them fails to parse
+// as soon as that is known.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    // Bytes in the request (or status) line, without the line ending.
+    pub request_line: usize,
+    pub header_count: usize,
+    // Bytes in the header section, including the line endings.
+    pub header_bytes: usize,
+    // Bytes of body content, without chunk framing.
+    pub body: usize,
+}
+
+impl Limits {
+    pub fn unlimited() -> Limits {
+        Limits {
+            request_line: usize::MAX,
+            header_count: usize::MAX,
+            header_bytes: usize::MAX,
+            body: usize::MAX,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            request_line: 8 * 1024,
+            header_count: 100,
+            header_bytes: 64 * 1024,
+            body: 100 * 1024 * 1024,
+        }
+    }
+}
+
+// Where the parser is in a chunked body.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Chunk {
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailers,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Request,
+    // Responses to HEAD have no body, whatever their headers say.
+    Response { head: bool },
+}
+
+// Push parser for one message: feed it bytes as they are read and it tells
+// when the message is complete. The bytes are kept until the message is
+// handed out with finish, or handed out as they are parsed with drain when
+// the message is streamed.
+#[derive(Debug)]
+pub struct MessageParser {
+    kind: Kind,
+    limits: Limits,
+    state: ParseState,
+    chunk: Chunk,
+    buf: BytesMut,
+    // Everything before the cursor is parsed.
+    cursor: usize,
+    // Start of the header section, after the start line.
+    head_start: usize,
+    // Status code of the response being parsed.
+    status: Option<u16>,
+    // The body without chunk framing, as ranges of the parsed bytes.
+    content: Vec<Range<usize>>,
+    // Counted against the limits, trailers count as headers.
+    header_count: usize,
+    header_bytes: usize,
+    body_length: usize,
+}
+
+impl MessageParser {
+    pub fn request() -> MessageParser {
+        return MessageParser::new(Kind::Request);
+    }
+
+    // Parses the response to a request with this method.
+    pub fn response(method: &HttpMethod) -> MessageParser {
+        return MessageParser::new(Kind::Response { head: *method == HttpMethod::Head });
+    }
+
+    fn new(kind: Kind) -> MessageParser {
+        MessageParser {
+            kind,
+            limits: Limits::unlimited(),
+            state: ParseState::RequestLine,
+            chunk: Chunk::Size,
+            buf: BytesMut::new(),
+            cursor: 0,
+            head_start: 0,
+            status: None,
+            content: Vec::new(),
+            header_count: 0,
+            header_bytes: 0,
+            body_length: 0,
+        }
+    }
+
+    // Fails messages that are over these limits, there are none by default.
+    pub fn with_limits(mut self, limits: Limits) -> MessageParser {
+        self.limits = limits;
+        return self;
+    }
+
+    pub fn state(&self) -> ParseState {
+        return self.state;
+    }
+
+    // Whether the header section has been parsed, so the body can be
+    // streamed.
+    pub fn has_head(&self) -> bool {
+        return !matches!(self.state, ParseState::RequestLine | ParseState::Headers);
+    }
+
+    // The body content found so far, as ranges of the bytes that were
+    // scanned or are not drained yet.
+    pub fn content(&self) -> &[Range<usize>] {
+        return &self.content;
+    }
+
+    // Adds the next bytes of the message and parses as far as they go.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<ParseState, HttpError> {
+        self.buf.extend_from_slice(bytes);
+
+        let buf = std::mem::take(&mut self.buf);
+        let result = self.advance(&buf);
+        self.buf = buf;
+        return result;
+    }
+
+    // Parses a message that is already in memory without copying it. The
+    // content ranges are offsets into these bytes.
+    pub fn scan(&mut self, bytes: &[u8]) -> Result<ParseState, HttpError> {
+        return self.advance(bytes);
+    }
+
+    // Hands out the bytes parsed so far and the body content in them, and
+    // forgets them so a large message is never kept whole. Nothing is handed
+    // out before the header section is complete.
+    pub fn drain(&mut self) -> (Bytes, Vec<Range<usize>>) {
+        if !self.has_head() {
+            return (Bytes::new(), Vec::new());
+        }
+        let bytes = self.buf.split_to(self.cursor).freeze();
+        self.cursor = 0;
+        self.head_start = 0;
+        return (bytes, std::mem::take(&mut self.content));
+    }
+
+    fn advance(&mut self, buf: &[u8]) -> Result<ParseState, HttpError> {
+        loop {
+            let before = (self.state, self.chunk, self.cursor);
+            self.step(buf)?;
+            if (self.state, self.chunk, self.cursor) == before {
+                return Ok(self.state);
+            }
+        }
+    }
+
+    // The peer closed the connection: a response without a length is complete
+    // now, any other message that is not complete was cut off.
+    pub fn close(&mut self) -> Result<ParseState, HttpError> {
+        if self.state == ParseState::UntilClose {
+            self.state = ParseState::Done;
+        }
+        if self.state != ParseState::Done {
+            return Err(HttpError::Incomplete);
+        }
+        return Ok(self.state);
+    }
+
+    // Ends the message, e.g. because the peer closed the connection. Returns
+    // the message when it is complete; bytes after it are kept for the next
+    // message.
+    pub fn finish(&mut self) -> Result<Bytes, HttpError> {
+        self.close()?;
+
+        let message = self.buf.split_to(self.cursor).freeze();
+        self.state = ParseState::RequestLine;
+        self.chunk = Chunk::Size;
+        self.cursor = 0;
+        self.head_start = 0;
+        self.status = None;
+        self.content.clear();
+        self.header_count = 0;
+        self.header_bytes = 0;
+        self.body_length = 0;
+        return Ok(message);
+    }
+
+    // The next line from the cursor without its line ending, and the
+    // position after it.
+    fn line<'a>(&self, buf: &'a [u8]) -> Option<(&'a [u8], usize)> {
+        let lf = buf[self.cursor..].iter().position(|&byte| byte == 0x0A)?;
+        let line = &buf[self.cursor..self.cursor + lf];
+        return Some((line.strip_suffix(b"\r").unwrap_or(line), self.cursor + lf + 1));
+    }
+
+    // Makes one step of progress if the buffered bytes allow it.
+    fn step(&mut self, buf: &[u8]) -> Result<(), HttpError> {
+        match self.state {
+            ParseState::RequestLine => {
+                let (line, next) = match self.line(buf) {
+                    Some(line) => line,
+                    // Allows for the CR of a line ending that is cut off.
+                    None if buf.len() - self.cursor > self.limits.request_line.saturating_add(1) => {
+                        return Err(HttpError::RequestLineTooLong);
+                    }
+                    None => return Ok(()),
+                };
+                if line.len() > self.limits.request_line {
+                    return Err(HttpError::RequestLineTooLong);
+                }
+                if let Kind::Response { .. } = self.kind {
+                    let sp = line.iter().position(|&byte| byte == 0x20).ok_or(HttpError::BadFormat)?;
+                    let code = line.get(sp + 1..sp + 4).map(HttpStatusCode::from);
+                    self.status = Some(code.and_then(|c| c.code()).ok_or(HttpError::BadFormat)?);
+                }
+                self.cursor = next;
+                self.head_start = next;
+                self.header_count = 0;
+                self.header_bytes = 0;
+                self.state = ParseState::Headers;
+            }
+            ParseState::Headers => {
+                let (line, next) = match self.line(buf) {
+                    Some(line) => line,
+                    None if buf.len() - self.head_start > self.limits.header_bytes => {
+                        return Err(HttpError::HeadersTooLarge);
+                    }
+                    None => return Ok(()),
+                };
+                if next - self.head_start > self.limits.header_bytes {
+                    return Err(HttpError::HeadersTooLarge);
+                }
+                let end_of_head = line.is_empty();
+                if !end_of_head {
+                    self.header_count += 1;
+                    if self.header_count > self.limits.header_count {
+                        return Err(HttpError::TooManyHeaders);
+                    }
+                }
+                self.cursor = next;
+                if end_of_head {
+                    self.header_bytes = next - self.head_start;
+                    self.state = self.framing(buf)?;
+                }
+            }
+            ParseState::Body(remaining) => {
+                let n = std::cmp::min(remaining, buf.len() - self.cursor);
+                self.add_content(n);
+                self.state = match remaining - n {
+                    0 => ParseState::Done,
+                    remaining => ParseState::Body(remaining),
+                };
+            }
+            ParseState::Chunked => self.step_chunk(buf)?,
+            ParseState::UntilClose => self.add_content(buf.len() - self.cursor),
+            ParseState::Done => {}
+        }
+        return Ok(());
+    }
+
+    fn step_chunk(&mut self, buf: &[u8]) -> Result<(), HttpError> {
+        match self.chunk {
+            Chunk::Size => {
+                let (line, next) = match self.line(buf) {
+                    Some(line) => line,
+                    None if buf.len() - self.cursor > MAX_CHUNK_LINE => return Err(HttpError::BadFraming),
+                    None => return Ok(()),
+                };
+                if next - self.cursor > MAX_CHUNK_LINE {
+                    return Err(HttpError::BadFraming);
+                }
+                // chunk-size [ chunk-ext ] CRLF
+                let size = line.split(|&byte| byte == b';').next().unwrap_or_default().trim_ascii();
+                let size = std::str::from_utf8(size)
+                    .ok()
+                    .and_then(|size| usize::from_str_radix(size, 16).ok())
+                    .ok_or(HttpError::BadFraming)?;
+                self.body_length = self.body_length.saturating_add(size);
+                if self.body_length > self.limits.body {
+                    return Err(HttpError::BodyTooLarge);
+                }
+                self.cursor = next;
+                self.chunk = match size {
+                    0 => Chunk::Trailers,
+                    size => Chunk::Data(size),
+                };
+            }
+            Chunk::Data(remaining) => {
+                let n = std::cmp::min(remaining, buf.len() - self.cursor);
+                self.add_content(n);
+                self.chunk = match remaining - n {
+                    0 => Chunk::DataEnd,
+                    remaining => Chunk::Data(remaining),
+                };
+            }
+            Chunk::DataEnd => match &buf[self.cursor..] {
+                [] | [b'\r'] => {}
+                [b'\n', ..] => {
+                    self.cursor += 1;
+                    self.chunk = Chunk::Size;
+                }
+                [b'\r', b'\n', ..] => {
+                    self.cursor += 2;
+                    self.chunk = Chunk::Size;
+                }
+                _ => return Err(HttpError::BadFraming),
+            },
+            Chunk::Trailers => {
+                let (line, next) = match self.line(buf) {
+                    Some(line) => line,
+                    None if self.header_bytes + (buf.len() - self.cursor) > self.limits.header_bytes => {
+                        return Err(HttpError::HeadersTooLarge);
+                    }
+                    None => return Ok(()),
+                };
+                self.header_bytes += next - self.cursor;
+                if self.header_bytes > self.limits.header_bytes {
+                    return Err(HttpError::HeadersTooLarge);
+                }
+                let end = line.is_empty();
+                if !end {
+                    self.header_count += 1;
+                    if self.header_count > self.limits.header_count {
+                        return Err(HttpError::TooManyHeaders);
+                    }
+                }
+                self.cursor = next;
+                if end {
+                    self.state = ParseState::Done;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    // Moves the cursor over n bytes of body content.
+    fn add_content(&mut self, n: usize) {
+        if n == 0 {
+            return;
+        }
+        let range = self.cursor..self.cursor + n;
+        match self.content.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => self.content.push(range),
+        }
+        self.cursor += n;
+    }
+
+    // The Content-Length and Transfer-Encoding fields of the header section,
+    // which ends at the cursor. Other fields that can not be decoded are left
+    // out, but in a request a malformed framing field fails: the proxy and
+    // main must agree on where the request ends.
+    fn framing_fields(&self, buf: &[u8]) -> Result<Vec<HeaderField>, HttpError> {
+        let framing = |header: &HttpHeader| matches!(header, HttpHeader::ContentLength | HttpHeader::TransferEncoding);
+        let mut fields: Vec<HeaderField> = Vec::new();
+        let mut cursor = self.head_start;
+        // Whether the previous line was a framing field, a folded line
+        // continues it.
+        let mut previous = false;
+
+        while let Some(lf) = buf[cursor..self.cursor].iter().position(|&byte| byte == 0x0A) {
+            let end = cursor + lf;
+            let line = &buf[cursor..end];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                break;
+            }
+            let name = line.split(|&byte| byte == b':').next().unwrap_or_default().trim_ascii();
+            let named = name.eq_ignore_ascii_case(b"content-length") || name.eq_ignore_ascii_case(b"transfer-encoding");
+
+            match decode_field(buf, cursor, end) {
+                Ok(field) => {
+                    previous = framing(&field.header);
+                    if previous {
+                        fields.push(field);
+                    }
+                }
+                Err(HeaderError::ObsoleteFolding) if previous && self.kind == Kind::Request => {
+                    return Err(HttpError::BadFraming);
+                }
+                Err(HeaderError::ObsoleteFolding) => {}
+                Err(_) if named && self.kind == Kind::Request => return Err(HttpError::BadFraming),
+                Err(_) => previous = false,
+            }
+            cursor = end + 1;
+        }
+        return Ok(fields);
+    }
+
+    // What follows the header section, which ends at the cursor.
+    fn framing(&mut self, buf: &[u8]) -> Result<ParseState, HttpError> {
+        if let Kind::Response { head } = self.kind {
+            match self.status.unwrap_or_default() {
+                // An interim response, the final response follows it.
+                100..=199 if self.status != Some(101) => return Ok(ParseState::RequestLine),
+                101 | 204 | 304 => return Ok(ParseState::Done),
+                _ if head => return Ok(ParseState::Done),
+                _ => {}
+            }
+        }
+
+        let fields = self.framing_fields(buf)?;
+        let value = |header: HttpHeader| {
+            fields
+                .iter()
+                .filter(move |f| f.header == header)
+                .map(|f| f.text(buf).into_owned())
+                .collect::<Vec<String>>()
+        };
+
+        // Transfer-Encoding wins over Content-Length in a response. A request
+        // with both is refused, main might pick the other one.
+        let codings = value(HttpHeader::TransferEncoding);
+        let lengths = value(HttpHeader::ContentLength);
+        if self.kind == Kind::Request && !codings.is_empty() && !lengths.is_empty() {
+            return Err(HttpError::BadFraming);
+        }
+        if !codings.is_empty() {
+            let last = codings.join(",");
+            let last = last.rsplit(',').next().unwrap_or_default().trim();
+            return match (last.eq_ignore_ascii_case("chunked"), self.kind) {
+                (true, _) => {
+                    self.chunk = Chunk::Size;
+                    Ok(ParseState::Chunked)
+                }
+                (false, Kind::Response { .. }) => Ok(ParseState::UntilClose),
+                (false, Kind::Request) => Err(HttpError::BadFraming),
+            };
+        }
+
+        // Repeated lengths are only accepted in a response, when they are all
+        // the same.
+        let mut length: Option<usize> = None;
+        for v in lengths.iter().flat_map(|v| v.split(',')) {
+            let n: usize = v.trim().parse().map_err(|_| HttpError::BadFraming)?;
+            if length.is_some() && (self.kind == Kind::Request || length != Some(n)) {
+                return Err(HttpError::BadFraming);
+            }
+            length = Some(n);
+        }
+
+        if length.is_some_and(|n| n > self.limits.body) {
+            return Err(HttpError::BodyTooLarge);
+        }
+        return match (length, self.kind) {
+            (Some(0), _) | (None, Kind::Request) => Ok(ParseState::Done),
+            (Some(n), _) => Ok(ParseState::Body(n)),
+            (None, Kind::Response { .. }) => Ok(ParseState::UntilClose),
+        };
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod test {
+    use super::*;
+
+    // Feeds the message in pieces of this size.
+    fn feed(parser: &mut MessageParser, message: &str, size: usize) -> Result<ParseState, HttpError> {
+        let mut state = parser.state();
+        for piece in message.as_bytes().chunks(size) {
+            state = parser.push(piece)?;
+        }
+        return Ok(state);
+    }
+
+    #[test]
+    fn request_with_length() {
+        let mut parser = MessageParser::request();
+        assert_eq!(parser.push(b"POST /a HTTP/1.1\r").unwrap(), ParseState::RequestLine);
+        assert_eq!(parser.push(b"\nContent-Length: 5\r\n").unwrap(), ParseState::Headers);
+        assert_eq!(parser.push(b"\r\nhel").unwrap(), ParseState::Body(2));
+        assert!(parser.finish().is_err());
+        assert_eq!(parser.push(b"loGET / HTTP/1.1\r\n\r\n").unwrap(), ParseState::Done);
+        assert_eq!(parser.finish().unwrap(), "POST /a HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello");
+
+        // The pipelined request is kept for the next message.
+        assert_eq!(parser.push(b"").unwrap(), ParseState::Done);
+        assert_eq!(parser.finish().unwrap(), "GET / HTTP/1.1\r\n\r\n");
+    }
+
+    #[test]
+    fn chunked_byte_by_byte() {
+        let message = "POST / HTTP/1.1\nTransfer-Encoding: gzip, chunked\n\n5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nTrailer: x\r\n\r\n";
+        for size in [1, 3, message.len()] {
+            let mut parser = MessageParser::request();
+            assert_eq!(feed(&mut parser, message, size).unwrap(), ParseState::Done);
+            assert_eq!(parser.finish().unwrap(), message);
+        }
+
+        let mut parser = MessageParser::request();
+        assert_eq!(feed(&mut parser, "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel", 4).unwrap(), ParseState::Chunked);
+        assert!(parser.push(b"lo!!").is_err());
+        assert!(feed(&mut MessageParser::request(), "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n", 64).is_err());
+    }
+
+    #[test]
+    fn request_framing_errors() {
+        let bad = [
+            "POST / HTTP/1.1\r\nContent-Length: 1x\r\n\r\n",
+            "POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n",
+        ];
+        for message in bad {
+            assert!(feed(&mut MessageParser::request(), message, 64).is_err(), "{}", message);
+        }
+    }
+
+    #[test]
+    fn request_smuggling() {
+        let bad = [
+            // Both lengths.
+            "POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 0\r\n\r\n0\r\n\r\n",
+            // Duplicate lengths, even when they agree.
+            "POST / HTTP/1.1\r\nContent-Length: 1, 1\r\n\r\nx",
+            "POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 1\r\n\r\nx",
+            // Unparseable lengths and malformed framing lines.
+            "POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+            "POST / HTTP/1.1\r\nContent-Length : 5\r\n\r\nhello",
+            "POST / HTTP/1.1\r\nContent-Length: 5\x00\r\n\r\nhello",
+            "POST / HTTP/1.1\r\nTransfer-Encoding : chunked\r\n\r\n0\r\n\r\n",
+            "POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n chunked\r\n\r\n0\r\n\r\n",
+            // Not chunked at the end.
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n",
+        ];
+        for message in bad {
+            assert!(
+                matches!(feed(&mut MessageParser::request(), message, 64), Err(HttpError::BadFraming)),
+                "{:?}",
+                message
+            );
+        }
+
+        // Other malformed lines are left out.
+        let message = "POST / HTTP/1.1\r\nX-Thing : 1\r\nContent-Length: 2\r\n\r\nok";
+        assert_eq!(feed(&mut MessageParser::request(), message, 64).unwrap(), ParseState::Done);
+        // A response may repeat its length.
+        let message = "HTTP/1.1 200 OK\r\nContent-Length: 2, 2\r\n\r\nok";
+        assert_eq!(feed(&mut MessageParser::response(&HttpMethod::Get), message, 64).unwrap(), ParseState::Done);
+    }
+
+    #[test]
+    fn limits() {
+        let limits = Limits {
+            request_line: 21,
+            header_count: 2,
+            header_bytes: 32,
+            body: 8,
+        };
+        let parse = |message: &str| feed(&mut MessageParser::request().with_limits(limits), message, 3);
+
+        assert!(parse("GET /0123456 HTTP/1.1\r\nA: b\r\nC: d\r\n\r\n").is_ok());
+        assert!(matches!(parse("GET /01234567 HTTP/1.1\r\n"), Err(HttpError::RequestLineTooLong)));
+        // Known before the line ends.
+        assert!(matches!(parse("GET /01234567890123456789"), Err(HttpError::RequestLineTooLong)));
+        assert!(matches!(parse("GET / HTTP/1.1\r\nA: b\r\nC: d\r\nE: f\r\n"), Err(HttpError::TooManyHeaders)));
+        assert!(matches!(parse("GET / HTTP/1.1\r\nCookie: 01234567890123456789012345678901"), Err(HttpError::HeadersTooLarge)));
+
+        assert!(parse("POST / HTTP/1.1\r\nContent-Length: 8\r\n\r\n01234567").is_ok());
+        assert!(matches!(parse("POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\n"), Err(HttpError::BodyTooLarge)));
+        let chunked = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n4\r\n";
+        assert!(matches!(parse(chunked), Err(HttpError::BodyTooLarge)));
+
+        // A chunk-size line and trailers without an end.
+        let chunked = "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let endless = format!("{}1{}", chunked, "0".repeat(MAX_CHUNK_LINE + 1));
+        assert!(matches!(parse(&endless), Err(HttpError::BadFraming)));
+        let endless = format!("{}0;{}", chunked, ";".repeat(MAX_CHUNK_LINE + 1));
+        assert!(matches!(feed(&mut MessageParser::request(), &endless, 64), Err(HttpError::BadFraming)));
+        // Trailers count with the header section.
+        let limits = Limits {
+            header_count: 2,
+            header_bytes: 48,
+            ..Limits::unlimited()
+        };
+        let parse = |message: &str| feed(&mut MessageParser::request().with_limits(limits), message, 3);
+        assert!(parse(&format!("{}0\r\nA: b\r\n\r\n", chunked)).is_ok());
+        assert!(matches!(parse(&format!("{}0\r\nA: b\r\nC: d\r\n", chunked)), Err(HttpError::TooManyHeaders)));
+        assert!(matches!(parse(&format!("{}0\r\nA: 01234567890123456789", chunked)), Err(HttpError::HeadersTooLarge)));
+
+        // Responses are not limited.
+        let response = "HTTP/1.1 200 OK\r\nContent-Length: 100000\r\n\r\n";
+        assert!(feed(&mut MessageParser::response(&HttpMethod::Get), response, 64).is_ok());
+    }
+
+    #[test]
+    fn responses() {
+        let mut parser = MessageParser::response(&HttpMethod::Get);
+        assert_eq!(feed(&mut parser, "HTTP/1.1 200 OK\r\nServer: x\r\n\r\nsome", 5).unwrap(), ParseState::UntilClose);
+        assert_eq!(parser.finish().unwrap(), "HTTP/1.1 200 OK\r\nServer: x\r\n\r\nsome");
+
+        let message = "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n";
+        let mut parser = MessageParser::response(&HttpMethod::Head);
+        assert_eq!(feed(&mut parser, message, 7).unwrap(), ParseState::Done);
+
+        let message = "HTTP/1.1 304 Not Modified\r\nContent-Length: 10\r\n\r\n";
+        assert_eq!(feed(&mut MessageParser::response(&HttpMethod::Get), message, 7).unwrap(), ParseState::Done);
+
+        // The interim response is part of the message.
+        let message = "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let mut parser = MessageParser::response(&HttpMethod::Post);
+        assert_eq!(feed(&mut parser, message, 9).unwrap(), ParseState::Done);
+        assert_eq!(parser.finish().unwrap(), message);
+
+        assert!(MessageParser::response(&HttpMethod::Get).push(b"HTTP/1.1 OK\r\n").is_err());
+    }
+
+    #[test]
+    fn streaming_content() {
+        let mut parser = MessageParser::request();
+        parser.push(b"POST / HTTP/1.1\r\nTransfer-").unwrap();
+        assert_eq!(parser.drain().0, "");
+        parser.push(b"Encoding: chunked\r\n\r\n3\r\nabc\r\n4\r\nde").unwrap();
+        let (bytes, content) = parser.drain();
+        let text: Vec<&[u8]> = content.iter().map(|r| &bytes[r.clone()]).collect();
+        assert_eq!(text, vec![&b"abc"[..], &b"de"[..]]);
+
+        assert_eq!(parser.push(b"fg\r\n0\r\n\r\n").unwrap(), ParseState::Done);
+        let (bytes, content) = parser.drain();
+        assert_eq!(bytes, "fg\r\n0\r\n\r\n");
+        assert_eq!(content, vec![0..2]);
+    }
+
+    #[test]
+    fn scan_in_place() {
+        let message = b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nbodyextra";
+        let mut parser = MessageParser::response(&HttpMethod::Get);
+        assert_eq!(parser.scan(message).unwrap(), ParseState::Done);
+        assert_eq!(parser.content(), &[38..42]);
+    }
+}
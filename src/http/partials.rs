@@ -1,62 +1,31 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
 use crate::http::error::*;
 
-#[derive(Debug, PartialEq)]
-pub enum HttpHeader {
-    Accept,
-    AcceptPatch,
-    AcceptRanges,
-    AccessControlAllowOrigin,
-    AccessControlAllowCredentials,
-    AccessControlAllowMethods,
-    AccessControlAllowHeaders,
-    AccessControlExposeHeaders,
-    AccessControlMaxAge,
-    Age,
-    Allow,
-    AltSvc,
-    CacheControl,
-    Connection,
-    ContentDisposition,
-    ContentEncoding,
-    ContentLanguage,
-    ContentLength,
-    ContentLocation,
-    ContentRange,
-    ContentType,
-    ContentSecurityPolicy,
-    Date,
-    DeltaBase,
-    ETag,
-    Expires,
-    IM,
-    LastModified,
-    Link,
-    Location,
-    Pragma,
-    ProxyAuthenticate,
-    PublicKeyPins,
-    RetryAfter,
-    Refresh,
-    Server,
-    SetCookie,
-    StrictTransportSecurity,
-    Trailer,
-    TransferEncoding,
-    Tk,
-    Upgrade,
-    Vary,
-    Via,
-    Warning,
-    WWWAuthenticate,
-    XPoweredBy,
-    XRequestID,
-    XUACompatible,
-    XXSSProtection,
-}
+// The HttpHeader enum, its field names and the lookup table used by
+// decoders::recognize are generated by build.rs from the table in fields.txt.
+include!(concat!(env!("OUT_DIR"), "/fields.rs"));
 
 pub type HttpHeaderPair = (HttpHeader,String);
 
-#[derive(Debug, PartialEq)]
+// A known header of a decoded message. The value is not copied out of the
+// message, it is the range of the trimmed value in the raw bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeaderField {
+    pub header: HttpHeader,
+    pub value: Range<usize>,
+}
+
+impl HeaderField {
+    // The value in the bytes of the message it was decoded from. obs-text is
+    // not UTF-8, it is replaced.
+    pub fn text<'a>(&self, bytes: &'a [u8]) -> Cow<'a, str> {
+        String::from_utf8_lossy(bytes.get(self.value.clone()).unwrap_or_default())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum HttpVersion {
     Http10,
     Http11,
@@ -64,48 +33,76 @@ pub enum HttpVersion {
     Http3, /* TODO: maybe not support this? */
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum HttpMethod {
     Options,
     Get,
     Head,
     Post,
     Put,
+    Patch,
     Delete,
     Trace,
     Connect,
+    // Any other valid method token, e.g. PROPFIND from WebDAV. Methods are
+    // case sensitive so this is also where "get" ends up.
+    Extension(String),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum HttpStatusCode {
     Ok200,
+    Other(u16),
     Unknown,
 }
 
+impl HttpStatusCode {
+    // The numeric status code, None when the status line could not be read.
+    pub fn code(&self) -> Option<u16> {
+        match self {
+            HttpStatusCode::Ok200 => Some(200),
+            HttpStatusCode::Other(n) => Some(*n),
+            HttpStatusCode::Unknown => None,
+        }
+    }
+}
+
 impl From<&[u8]> for HttpStatusCode {
     fn from(value: &[u8]) -> Self {
         match value {
             [0x32, 0x30, 0x30] => HttpStatusCode::Ok200,
+            [a @ b'1'..=b'5', b @ b'0'..=b'9', c @ b'0'..=b'9'] => {
+                let n = (*a - b'0') as u16 * 100 + (*b - b'0') as u16 * 10 + (*c - b'0') as u16;
+                HttpStatusCode::Other(n)
+            }
             _ => Self::Unknown,
         }
     }
 }
 
-impl From<HttpMethod> for &str {
-    fn from(value: HttpMethod) -> Self {
-        match value {
+impl HttpMethod {
+    pub fn as_str(&self) -> &str {
+        match self {
             HttpMethod::Options => "OPTIONS",
             HttpMethod::Get => "GET",
             HttpMethod::Head => "HEAD",
             HttpMethod::Post => "POST",
             HttpMethod::Put => "PUT",
+            HttpMethod::Patch => "PATCH",
             HttpMethod::Delete => "DELETE",
             HttpMethod::Trace => "TRACE",
             HttpMethod::Connect => "CONNECT",
+            HttpMethod::Extension(method) => method.as_str(),
         }
     }
 }
 
+impl std::fmt::Display for HttpMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
 impl TryFrom<&[u8]> for HttpVersion {
     type Error = HttpError;
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
@@ -150,20 +147,61 @@ impl From<HttpVersion> for &str {
     }
 }
 
-impl TryFrom<&str> for HttpMethod {
+/* The method is a token, see:
+ * https://httpwg.org/specs/rfc9110.html#tokens
+ */
+impl TryFrom<&[u8]> for HttpMethod {
     type Error = HttpError;
 
-    fn try_from(value: &str) -> Result<Self, Self::Error> {
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
         match value {
-            "OPTIONS" => Ok(HttpMethod::Options),
-            "GET" => Ok(HttpMethod::Get),
-            "HEAD" => Ok(HttpMethod::Head),
-            "POST" => Ok(HttpMethod::Post),
-            "PUT" => Ok(HttpMethod::Put),
-            "DELETE" => Ok(HttpMethod::Delete),
-            "TRACE" => Ok(HttpMethod::Trace),
-            "CONNECT" => Ok(HttpMethod::Connect),
-            _ => Err(HttpError::UnknownVersion),
+            b"OPTIONS" => Ok(HttpMethod::Options),
+            b"GET" => Ok(HttpMethod::Get),
+            b"HEAD" => Ok(HttpMethod::Head),
+            b"POST" => Ok(HttpMethod::Post),
+            b"PUT" => Ok(HttpMethod::Put),
+            b"PATCH" => Ok(HttpMethod::Patch),
+            b"DELETE" => Ok(HttpMethod::Delete),
+            b"TRACE" => Ok(HttpMethod::Trace),
+            b"CONNECT" => Ok(HttpMethod::Connect),
+            _ if !value.is_empty() && value.iter().all(|&b| is_tchar(b)) => {
+                /* only ASCII, so this is valid UTF-8 */
+                Ok(HttpMethod::Extension(String::from_utf8_lossy(value).into_owned()))
+            }
+            _ => Err(HttpError::BadFormat),
         }
     }
 }
+
+impl TryFrom<&str> for HttpMethod {
+    type Error = HttpError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        HttpMethod::try_from(value.as_bytes())
+    }
+}
+
+// tchar = "!" / "#" / "$" / "%" / "&" / "'" / "*" / "+" / "-" / "." /
+//         "^" / "_" / "`" / "|" / "~" / DIGIT / ALPHA
+pub fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn methods() {
+        assert_eq!(HttpMethod::try_from("PATCH").unwrap(), HttpMethod::Patch);
+        assert_eq!(HttpMethod::try_from("POST").unwrap(), HttpMethod::Post);
+        assert_eq!(
+            HttpMethod::try_from("PROPFIND").unwrap(),
+            HttpMethod::Extension(String::from("PROPFIND"))
+        );
+        assert_eq!(HttpMethod::try_from("get").unwrap().as_str(), "get");
+        assert!(HttpMethod::try_from("").is_err());
+        assert!(HttpMethod::try_from("GE T").is_err());
+        assert!(HttpMethod::try_from("GET/").is_err());
+    }
+}
@@ -1,99 +1,264 @@
-use std::collections::HashMap;
+use std::ops::Range;
 
+use bytes::{Bytes, BytesMut};
+
+use crate::http::decoders::decode_fields;
 use crate::http::error::HttpError;
-use crate::http::partials::{HttpMethod, HttpVersion};
+use crate::http::parser::MessageParser;
+use crate::http::partials::{HeaderField, HttpHeader, HttpMethod, HttpVersion};
+use crate::http::response::content;
+use crate::http::tee::BodyDigest;
+use crate::http::target::{RequestTarget, TargetForm};
+
+// Header that carries the correlation ID of an exchange to main, the shadows
+// and the stored results.
+pub const REQUEST_ID_HEADER: &str = "X-Request-ID";
 
 /* Request Line grammar can be found here:
  * https://httpwg.org/specs/rfc9112.html#message.format
  */
 
+// A view on a raw request: the request line and headers are ranges into its
+// bytes and the body is a slice of them.
 #[derive(Debug)]
 pub struct DecodedHttpRequest {
-    size: usize,
+    pub size: usize,
+    line: RequestLine,
+    pub target: RequestTarget,
+    pub fields: Vec<HeaderField>,
+    pub body: Bytes,
+    pub bytes: Bytes,
+}
+
+impl DecodedHttpRequest {
+    pub fn method(&self) -> &HttpMethod {
+        return &self.line.method;
+    }
+
+    // The target as it was received.
+    pub fn raw_target(&self) -> &str {
+        return self.line.target(&self.bytes);
+    }
+
+    pub fn version(&self) -> HttpVersion {
+        return self.line.version;
+    }
+
+    // The value of the first header of this kind.
+    pub fn header(&self, header: &HttpHeader) -> Option<&[u8]> {
+        let field = self.fields.iter().find(|f| f.header == *header)?;
+        return self.bytes.get(field.value.clone());
+    }
+}
+
+// Where the parts of the request line are in the bytes of a request.
+#[derive(Debug, Clone, PartialEq)]
+struct RequestLine {
     method: HttpMethod,
-    target: String,
+    // Checked to be UTF-8 when parsed.
+    target: Range<usize>,
     version: HttpVersion,
-    headers: HashMap<String, String>,
+    // Start of the header section.
+    end: usize,
+}
+
+impl RequestLine {
+    fn parse(bytes: &[u8]) -> Result<RequestLine, HttpError> {
+        let sp = bytes.iter().position(|&byte| byte == 0x20).ok_or(HttpError::BadFormat)?;
+        let method = HttpMethod::try_from(&bytes[..sp])?;
+        let start = sp + 1;
+
+        let sp = bytes[start..]
+            .iter()
+            .position(|&byte| byte == 0x20)
+            .ok_or(HttpError::BadFormat)?;
+        let target = start..start + sp;
+        std::str::from_utf8(&bytes[target.clone()]).map_err(|_| HttpError::BadFormat)?;
+
+        let start = target.end + 1;
+        let lf = bytes[start..]
+            .iter()
+            .position(|&byte| byte == 0x0A)
+            .ok_or(HttpError::BadFormat)?;
+        let version: HttpVersion = bytes[start..start + lf].try_into()?;
+
+        Ok(RequestLine {
+            method,
+            target,
+            version,
+            end: start + lf + 1,
+        })
+    }
+
+    fn target<'a>(&self, bytes: &'a [u8]) -> &'a str {
+        return std::str::from_utf8(&bytes[self.target.clone()]).unwrap_or_default();
+    }
 }
 
-#[derive(Debug, Default)]
+// The request as it was read from the client. The bytes are shared by main,
+// every shadow, the comparison and the store, so cloning a request does not
+// copy it. Changes to the request build a new buffer.
+#[derive(Debug, Clone)]
 pub struct RawHttpRequest {
-    pub bytes: Vec<u8>,
+    pub bytes: Bytes,
     pub size: usize,
+    // Set when the request was larger than the tee limit. It was streamed to
+    // main and only the start of it was kept, so it can not be mirrored.
+    pub digest: Option<BodyDigest>,
+    // The request line, parsed when the request is created and again when
+    // its bytes are changed.
+    line: Result<RequestLine, HttpError>,
 }
 
-impl RawHttpRequest {
-    // Add bytes to the http request, return whether the http request is fully
-    // parsed after this addition.
-    pub fn add_bytes(&mut self, bytes: &[u8], n: usize) {
-        self.bytes.extend_from_slice(bytes);
-        self.size += n;
-    }
-
-    pub fn decode(self) -> Result<DecodedHttpRequest, HttpError> {
-        // TODO: parsing could be done more efficiently.
-        // e.g.: iterateover the bytes and find the spaces, when spaces are
-        // found, do something with the parts in between.
-
-        // NOTE: many assumptions are made here which will probably not hold
-        // in a valid environment. Should add proper error handling.
-
-        let mut cursor: usize = 0;
-        let method: HttpMethod;
-        let target: String;
-
-        if self.bytes.len() > 1 {
-            method = match self.bytes[0] {
-                0x43 => Ok(HttpMethod::Connect),
-                0x44 => Ok(HttpMethod::Delete),
-                0x47 => Ok(HttpMethod::Get),
-                0x48 => Ok(HttpMethod::Head),
-                0x4F => Ok(HttpMethod::Options),
-                0x50 => match self.bytes[1] {
-                    0x4F => Ok(HttpMethod::Post),
-                    0x55 => Ok(HttpMethod::Put),
-                    _ => Err(HttpError::BadFormat),
-                },
-                0x54 => Ok(HttpMethod::Trace),
-                _ => Err(HttpError::BadFormat),
-            }?;
-
-            if let Some(sp) = self.bytes.iter().position(|&byte| byte == 0x20) {
-                cursor = sp + 1;
-            }
-        } else {
-            return Err(HttpError::BadFormat);
-        }
-
-        let next_sp = self.bytes[cursor..].iter().position(|&byte| byte == 0x20);
-
-        if let Some(sp) = next_sp {
-            let range = cursor..cursor + sp;
-            target = std::str::from_utf8(&self.bytes[range])
-                .expect("expect the request line to be valid")
-                .into();
+impl Default for RawHttpRequest {
+    fn default() -> Self {
+        RawHttpRequest::from(Bytes::new())
+    }
+}
 
-            cursor = cursor + sp + 1;
-        } else {
-            return Err(HttpError::BadFormat);
+impl From<Bytes> for RawHttpRequest {
+    fn from(value: Bytes) -> Self {
+        RawHttpRequest {
+            size: value.len(),
+            line: RequestLine::parse(&value),
+            bytes: value,
+            digest: None,
         }
+    }
+}
 
-        let next_cr = self.bytes[cursor..]
-            .iter()
-            .position(|&byte| byte == 0x0A)
-            .expect("request line should fit entirely when testing");
+impl From<Vec<u8>> for RawHttpRequest {
+    fn from(value: Vec<u8>) -> Self {
+        RawHttpRequest::from(Bytes::from(value))
+    }
+}
 
-        let range = cursor..cursor + next_cr;
-        let version: HttpVersion = self.bytes[range].try_into()?;
+impl RawHttpRequest {
+    pub fn decode(&self) -> Result<DecodedHttpRequest, HttpError> {
+        let line = self.line.clone()?;
+        let target = RequestTarget::parse(line.target(&self.bytes), &line.method)?;
+
+        let (fields, _) = decode_fields(&self.bytes, line.end);
+        let mut parser = MessageParser::request();
+        let _ = parser.scan(&self.bytes);
 
         Ok(DecodedHttpRequest {
             size: self.size,
-            method,
+            line,
             target,
-            version,
-            headers: HashMap::new(),
+            fields,
+            body: content(&self.bytes, parser.content()),
+            bytes: self.bytes.clone(),
         })
     }
+
+    // Replaces a range of the request with other bytes.
+    fn splice(&mut self, range: Range<usize>, with: &[u8]) {
+        let mut out = BytesMut::with_capacity(self.bytes.len() - range.len() + with.len());
+        out.extend_from_slice(&self.bytes[..range.start]);
+        out.extend_from_slice(with);
+        out.extend_from_slice(&self.bytes[range.end..]);
+        self.size = out.len();
+        self.bytes = out.freeze();
+        self.line = RequestLine::parse(&self.bytes);
+    }
+
+    // The method, target and version of the request, without decoding the
+    // rest of it, so the proxy can look at them before forwarding.
+    pub fn request_line(&self) -> Result<(&HttpMethod, &str, HttpVersion), HttpError> {
+        let line = self.line.as_ref().map_err(|e| *e)?;
+        return Ok((&line.method, line.target(&self.bytes), line.version));
+    }
+
+    // Adds a header directly after the request line.
+    pub fn insert_header(&mut self, name: &str, value: &str) {
+        let at = match self.bytes.iter().position(|&byte| byte == 0x0A) {
+            Some(lf) => lf + 1,
+            None => return,
+        };
+        let line = format!("{}: {}\r\n", name, value);
+        self.splice(at..at, line.as_bytes());
+    }
+
+    // Rewrites an absolute-form request, as sent by clients of a forward
+    // proxy, to the origin form upstreams expect. The Host header is replaced
+    // by the authority of the target. Other requests are left as they are.
+    pub fn normalize_target(&mut self) {
+        let line = match &self.line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let target = match RequestTarget::parse(line.target(&self.bytes), &line.method) {
+            Ok(target) if target.form == TargetForm::Absolute => target,
+            _ => return,
+        };
+
+        let range = line.target.clone();
+        let origin = target.origin();
+        self.splice(range, origin.as_bytes());
+
+        self.remove_header("host");
+        if let Some(authority) = &target.authority {
+            self.insert_header("Host", authority);
+        }
+    }
+
+    // Removes every header with this name (case insensitive) from the header
+    // section.
+    pub fn remove_header(&mut self, name: &str) {
+        let mut cursor = match self.bytes.iter().position(|&byte| byte == 0x0A) {
+            Some(lf) => lf + 1,
+            None => return,
+        };
+
+        while let Some(lf) = self.bytes[cursor..].iter().position(|&byte| byte == 0x0A) {
+            let end = cursor + lf + 1;
+            let line = &self.bytes[cursor..end - 1];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                return; /* end of the header section */
+            }
+            let matches = line
+                .iter()
+                .position(|&byte| byte == b':')
+                .is_some_and(|colon| line[..colon].eq_ignore_ascii_case(name.as_bytes()));
+            if matches {
+                self.splice(cursor..end, b"");
+            } else {
+                cursor = end;
+            }
+        }
+    }
+
+    // The correlation ID of the request. The X-Request-ID sent by the client
+    // is reused, otherwise a new ID is generated and added to the request so
+    // main and the shadows receive it too.
+    pub fn ensure_request_id(&mut self) -> String {
+        if let Some(id) = self.header(REQUEST_ID_HEADER).filter(|id| !id.is_empty()) {
+            return String::from(id);
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        self.insert_header(REQUEST_ID_HEADER, &id);
+        return id;
+    }
+
+    // Finds the value of the first header with the given name (case
+    // insensitive) in the header section.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let mut lines = self.bytes.split(|&byte| byte == 0x0A).skip(1);
+
+        lines.find_map(|line| {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                return Some(None); /* end of the header section */
+            }
+            let colon = line.iter().position(|&byte| byte == b':')?;
+            if !line[..colon].eq_ignore_ascii_case(name.as_bytes()) {
+                return None;
+            }
+            Some(std::str::from_utf8(&line[colon + 1..]).ok().map(|v| v.trim()))
+        })?
+    }
 }
 
 #[cfg(test)]
@@ -102,35 +267,117 @@ mod test {
 
     #[test]
     fn small_request_method() {
-        let mut rq: RawHttpRequest = RawHttpRequest::default();
         let payload =
             "GET /api HTTP/1.1\nHost: localhost:1234\nUser-Agent: curl/8.4.0\nAccept: */*\n\n";
-        let _ = rq.add_bytes(payload.as_bytes(), payload.len());
+        let rq = RawHttpRequest::from(Vec::from(payload));
         let rq = rq.decode().expect("should be decodable");
-        let method: HttpMethod = rq.method;
-        let asstr: &str = method.into();
-        assert_eq!(asstr, "GET");
+        let method: &HttpMethod = rq.method();
+        assert_eq!(method.as_str(), "GET");
+    }
+
+    #[test]
+    fn decoded_view() {
+        let payload = "POST /api HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nbody";
+        let raw = RawHttpRequest::from(Vec::from(payload));
+        let rq = raw.decode().expect("should be decodable");
+        assert_eq!(rq.header(&HttpHeader::Host), Some(&b"localhost"[..]));
+        assert_eq!(rq.header(&HttpHeader::Accept), None);
+        assert_eq!(rq.body, "body");
+        // Decoding and cloning share the buffer of the raw request.
+        assert_eq!(rq.body.as_ptr(), raw.bytes[payload.len() - 4..].as_ptr());
+        assert_eq!(raw.clone().bytes.as_ptr(), raw.bytes.as_ptr());
+    }
+
+    #[test]
+    fn full_method_token() {
+        let rq = RawHttpRequest::from(Vec::from("PATCH /api HTTP/1.1\r\n\r\n"));
+        assert_eq!(rq.decode().expect("should be decodable").method(), &HttpMethod::Patch);
+
+        let rq = RawHttpRequest::from(Vec::from("PROPFIND /dav HTTP/1.1\r\n\r\n"));
+        let decoded = rq.decode().expect("should be decodable");
+        assert_eq!(decoded.method(), &HttpMethod::Extension(String::from("PROPFIND")));
+
+        let rq = RawHttpRequest::from(Vec::from("POSTAL /api HTTP/1.1\r\n\r\n"));
+        assert_eq!(rq.decode().unwrap().method().as_str(), "POSTAL");
+
+        let rq = RawHttpRequest::from(Vec::from("G(T /api HTTP/1.1\r\n\r\n"));
+        assert!(rq.decode().is_err());
     }
 
     #[test]
     fn small_request_version() {
-        let mut rq: RawHttpRequest = RawHttpRequest::default();
         let payload =
             "GET /api HTTP/1.1\nHost: localhost:1234\nUser-Agent: curl/8.4.0\nAccept: */*\n\n";
-        let _ = rq.add_bytes(payload.as_bytes(), payload.len());
+        let rq = RawHttpRequest::from(Vec::from(payload));
         let rq = rq.decode().expect("should be decodable");
-        let version: HttpVersion = rq.version;
+        let version: HttpVersion = rq.version();
         let asstr: &str = version.into();
         assert_eq!(asstr, "HTTP/1.1");
     }
 
     #[test]
     fn small_request_target() {
-        let mut rq: RawHttpRequest = RawHttpRequest::default();
         let payload =
             "GET /api HTTP/1.1\nHost: localhost:1234\nUser-Agent: curl/8.4.0\nAccept: */*\n\n";
-        let _ = rq.add_bytes(payload.as_bytes(), payload.len());
+        let rq = RawHttpRequest::from(Vec::from(payload));
         let rq = rq.decode().expect("should be decodable");
-        assert_eq!(rq.target, "/api");
+        assert_eq!(rq.target.path, "/api");
+        assert_eq!(rq.raw_target(), "/api");
+    }
+
+    #[test]
+    fn absolute_form() {
+        let payload = "GET http://example.com:8080/api?x=1 HTTP/1.1\r\nHost: proxy\r\nAccept: */*\r\n\r\nbody";
+        let mut rq = RawHttpRequest::from(Vec::from(payload));
+        rq.normalize_target();
+        assert_eq!(
+            String::from_utf8_lossy(&rq.bytes),
+            "GET /api?x=1 HTTP/1.1\r\nHost: example.com:8080\r\nAccept: */*\r\n\r\nbody"
+        );
+        assert_eq!(rq.size, rq.bytes.len());
+
+        let payload = "GET /api HTTP/1.1\r\nHost: a\r\n\r\n";
+        let mut rq = RawHttpRequest::from(Vec::from(payload));
+        rq.normalize_target();
+        assert_eq!(String::from_utf8_lossy(&rq.bytes), payload);
+    }
+
+    #[test]
+    fn request_line_offsets() {
+        let mut rq = RawHttpRequest::from(Vec::from("GET http://a/x HTTP/1.1\r\nHost: b\r\n\r\n"));
+        let (method, target, version) = rq.request_line().unwrap();
+        assert_eq!((method, target, version), (&HttpMethod::Get, "http://a/x", HttpVersion::Http11));
+        // Borrowed from the bytes of the request.
+        assert_eq!(target.as_ptr(), rq.bytes[4..].as_ptr());
+
+        rq.normalize_target();
+        assert_eq!(rq.request_line().unwrap().1, "/x");
+        assert_eq!(rq.decode().unwrap().raw_target(), "/x");
+
+        assert!(RawHttpRequest::from(Vec::from("GET /x\r\n")).request_line().is_err());
+    }
+
+    #[test]
+    fn find_header() {
+        let payload = "GET /api HTTP/1.1\r\nHost: localhost:1234\r\nuser-agent:  curl/8.4.0 \r\n\r\nAccept: body";
+        let rq: RawHttpRequest = RawHttpRequest::from(Vec::from(payload));
+        assert_eq!(rq.header("host"), Some("localhost:1234"));
+        assert_eq!(rq.header("User-Agent"), Some("curl/8.4.0"));
+        assert_eq!(rq.header("Accept"), None);
+    }
+
+    #[test]
+    fn request_id() {
+        let mut rq = RawHttpRequest::from(Vec::from("GET / HTTP/1.1\r\nX-Request-Id: abc\r\n\r\n"));
+        assert_eq!(rq.ensure_request_id(), "abc");
+        assert_eq!(rq.size, rq.bytes.len());
+
+        let mut rq = RawHttpRequest::from(Vec::from("GET / HTTP/1.1\r\nHost: a\r\n\r\n"));
+        let id = rq.ensure_request_id();
+        assert_eq!(id.len(), 36);
+        assert_eq!(rq.header("x-request-id"), Some(id.as_str()));
+        assert_eq!(rq.header("host"), Some("a"));
+        assert_eq!(rq.size, rq.bytes.len());
+        assert_eq!(rq.ensure_request_id(), id);
     }
 }
@@ -1,50 +1,89 @@
+use std::borrow::Cow;
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+
 use super::{
     error::HttpError,
-    partials::{HttpHeader, HttpHeaderPair, HttpStatusCode, HttpVersion},
+    parser::MessageParser,
+    partials::{HeaderField, HttpHeader, HttpMethod, HttpStatusCode, HttpVersion},
+    tee::BodyDigest,
     decoders::*
 };
 
-#[derive(Debug)]
+#[derive(Debug, Default, Clone)]
 pub struct RawHttpResponse {
-    pub bytes: Vec<u8>,
+    // Shared with the decoded response, the capture file and the store, so
+    // cloning a response does not copy it.
+    pub bytes: Bytes,
     pub size: usize,
+    // Time between connecting to the upstream and receiving the full response,
+    // only known when the response came from request_server.
+    pub latency: Option<Duration>,
+    // Set when the response was larger than the tee limit, the bytes are
+    // then only the start of the response.
+    pub digest: Option<BodyDigest>,
 }
 
+// A view on a raw response: the headers are ranges into its bytes and the
+// body is a slice of them.
 #[derive(Debug)]
 pub struct DecodedHttpResponse {
     pub version: HttpVersion,
     pub status: HttpStatusCode,
-    pub headers: Vec<HttpHeaderPair>,
+    pub fields: Vec<HeaderField>,
     pub content_length: Option<usize>,
+    // Length of the status line and headers, the body starts after them.
+    pub head: usize,
+    pub body: Bytes,
+    pub bytes: Bytes,
+    // Compare by this instead of the body when set, see RawHttpResponse.
+    pub digest: Option<BodyDigest>,
 }
 
-impl From<Vec<u8>> for RawHttpResponse {
-    fn from(value: Vec<u8>) -> Self {
+impl From<Bytes> for RawHttpResponse {
+    fn from(value: Bytes) -> Self {
         RawHttpResponse {
             size: value.len(),
             bytes: value,
+            latency: None,
+            digest: None,
         }
     }
 }
 
-impl Default for RawHttpResponse {
-    fn default() -> Self {
-        RawHttpResponse {
-            size: Default::default(),
-            bytes: Default::default(),
-        }
+impl From<Vec<u8>> for RawHttpResponse {
+    fn from(value: Vec<u8>) -> Self {
+        RawHttpResponse::from(Bytes::from(value))
     }
 }
 
-impl Default for DecodedHttpResponse {
-    fn default() -> Self {
-        todo!();
+impl DecodedHttpResponse {
+    // The known headers with their values, in the order they were received.
+    pub fn headers(&self) -> impl Iterator<Item = (&HttpHeader, Cow<'_, str>)> {
+        self.fields.iter().map(|f| (&f.header, f.text(&self.bytes)))
     }
-}
 
+    pub fn header(&self, header: &HttpHeader) -> Option<Cow<'_, str>> {
+        self.fields.iter().find(|f| &f.header == header).map(|f| f.text(&self.bytes))
+    }
+}
 
 impl RawHttpResponse {
-    pub fn decode(self) -> Result<DecodedHttpResponse, HttpError> {
+    // Reads the status code from the status line without decoding the rest
+    // of the response.
+    pub fn status(&self) -> HttpStatusCode {
+        let sp = match self.bytes.iter().position(|&byte| byte == 0x20) {
+            Some(sp) => sp,
+            None => return HttpStatusCode::Unknown,
+        };
+        match self.bytes.get(sp + 1..sp + 4) {
+            Some(code) => code.into(),
+            None => HttpStatusCode::Unknown,
+        }
+    }
+
+    pub fn decode(&self) -> Result<DecodedHttpResponse, HttpError> {
         let next_sp = self.bytes.iter().position(|&byte| byte == 0x20);
         if next_sp.is_none() {
             return Err(HttpError::BadFormat);
@@ -56,46 +95,71 @@ impl RawHttpResponse {
             return Err(HttpError::BadFormat);
         }
         let version = version.unwrap();
-        let range = next_sp + 1..next_sp + 4;
-        let status: HttpStatusCode = self.bytes[range].into();
+        let status: HttpStatusCode = match self.bytes.get(next_sp + 1..next_sp + 4) {
+            Some(code) => code.into(),
+            None => return Err(HttpError::BadFormat),
+        };
 
         let next_lf = match self.bytes[next_sp + 4..].iter().position(|&byte| byte == 0x0A) {
             Some(n) => next_sp + 4 + n,
             None => {
-                return Ok(DecodedHttpResponse { version, status, headers: Vec::default(), content_length: Some(0)});
+                return Ok(DecodedHttpResponse {
+                    version,
+                    status,
+                    fields: Vec::default(),
+                    content_length: Some(0),
+                    head: self.bytes.len(),
+                    body: Bytes::new(),
+                    bytes: self.bytes.clone(),
+                    digest: self.digest.clone(),
+                });
             }
         };
 
-        let mut headers: Vec<HttpHeaderPair> = Vec::with_capacity(10);
-
-        let mut cursor = next_lf;
-        loop {
-            let next_lf = self.bytes[cursor..].iter().position(|&byte| byte == 0x0A);
-
-            if next_lf.is_none() {
-                break;
-            }
-
-            let line_length = next_lf.unwrap();
-
-            if let Some(header_pair) = decode_header(&self.bytes, cursor, cursor + line_length) {
-                headers.push(header_pair);
-            }
-
-            cursor = cursor + line_length + 1;
-        }
+        let (fields, head) = decode_fields(&self.bytes, next_lf + 1);
 
         let mut content_length: Option<usize> = None;
 
-        headers.iter().for_each(|header_pair| {
-            if header_pair.0 == HttpHeader::ContentLength {
-                if let Ok(n) = header_pair.1.parse::<usize>() {
+        fields.iter().for_each(|field| {
+            if field.header == HttpHeader::ContentLength {
+                if let Ok(n) = field.text(&self.bytes).parse::<usize>() {
                     content_length = Some(n);
                 }
             }
         });
 
-        Ok(DecodedHttpResponse { version, status, headers, content_length })
+        // Whatever the framing, the parser finds the body content. Bad framing
+        // leaves the body empty.
+        let mut parser = MessageParser::response(&HttpMethod::Get);
+        let _ = parser.scan(&self.bytes);
+        let body = content(&self.bytes, parser.content());
+
+        Ok(DecodedHttpResponse {
+            version,
+            status,
+            fields,
+            content_length,
+            head,
+            body,
+            bytes: self.bytes.clone(),
+            digest: self.digest.clone(),
+        })
+    }
+}
+
+// The body content of a message. Only a chunked body, which is in pieces, is
+// copied.
+pub fn content(bytes: &Bytes, ranges: &[std::ops::Range<usize>]) -> Bytes {
+    match ranges {
+        [] => Bytes::new(),
+        [range] => bytes.slice(range.clone()),
+        ranges => {
+            let mut out = BytesMut::with_capacity(ranges.iter().map(|r| r.len()).sum());
+            for range in ranges.iter() {
+                out.extend_from_slice(&bytes[range.clone()]);
+            }
+            out.freeze()
+        }
     }
 }
 
@@ -122,4 +186,34 @@ mod test {
         assert_eq!(actual.version, HttpVersion::Http11);
         assert_eq!(actual.status, HttpStatusCode::Ok200);
     }
+
+    #[test]
+    fn other_status() {
+        let payload = "HTTP/1.1 404 Not Found";
+        let raw: RawHttpResponse = RawHttpResponse::from(Vec::from(payload));
+        assert_eq!(raw.status(), HttpStatusCode::Other(404));
+        let actual = raw.decode().expect("decoding should work");
+
+        assert_eq!(actual.status, HttpStatusCode::Other(404));
+        assert_eq!(actual.status.code(), Some(404));
+    }
+
+    #[test]
+    fn body_after_headers() {
+        let payload = "HTTP/1.1 200 OK\r\nServer: testbed\r\n\r\nhello";
+        let raw: RawHttpResponse = RawHttpResponse::from(Vec::from(payload));
+        let actual = raw.decode().expect("decoding should work");
+
+        assert_eq!(actual.body, "hello");
+        assert_eq!(actual.headers().collect::<Vec<_>>(), vec![(&HttpHeader::Server, Cow::from("testbed"))]);
+        // The body is a view on the raw response, not a copy.
+        assert_eq!(actual.body.as_ptr(), raw.bytes[raw.bytes.len() - 5..].as_ptr());
+    }
+
+    #[test]
+    fn chunked_body() {
+        let payload = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhe\r\n3\r\nllo\r\n0\r\n\r\n";
+        let actual = RawHttpResponse::from(Vec::from(payload)).decode().expect("decoding should work");
+        assert_eq!(actual.body, "hello");
+    }
 }
@@ -0,0 +1,259 @@
+use crate::http::error::HttpError;
+use crate::http::partials::HttpMethod;
+
+/* The four forms of the request target are described here:
+ * https://httpwg.org/specs/rfc9112.html#request.target
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetForm {
+    // /path?query, the usual form.
+    Origin,
+    // http://host/path?query, sent by clients of a forward proxy.
+    Absolute,
+    // host:port, only for CONNECT.
+    Authority,
+    // *, only for a server wide OPTIONS.
+    Asterisk,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestTarget {
+    pub form: TargetForm,
+    // The target as it was received.
+    pub raw: String,
+    // Only set for the absolute form.
+    pub scheme: Option<String>,
+    // Host and port for the absolute and authority forms.
+    pub authority: Option<String>,
+    // Percent-decoded path, empty for the authority and asterisk forms.
+    pub path: String,
+    // Percent-decoded query parameters in order, keys can repeat.
+    pub query: Vec<(String, String)>,
+}
+
+impl RequestTarget {
+    pub fn parse(raw: &str, method: &HttpMethod) -> Result<RequestTarget, HttpError> {
+        let mut target = RequestTarget {
+            form: TargetForm::Origin,
+            raw: String::from(raw),
+            scheme: None,
+            authority: None,
+            path: String::new(),
+            query: Vec::new(),
+        };
+
+        if raw.is_empty() || raw.bytes().any(|b| b.is_ascii_control() || b == b' ') {
+            return Err(HttpError::BadTarget);
+        }
+
+        if *method == HttpMethod::Connect {
+            // authority-form = uri-host ":" port
+            let port = raw.rsplit_once(':').map(|(_, port)| port).unwrap_or_default();
+            if raw.contains(['/', '?', '#', '@']) || port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(HttpError::BadTarget);
+            }
+            target.form = TargetForm::Authority;
+            target.authority = Some(String::from(raw));
+            return Ok(target);
+        }
+
+        if raw == "*" {
+            if *method != HttpMethod::Options {
+                return Err(HttpError::BadTarget);
+            }
+            target.form = TargetForm::Asterisk;
+            return Ok(target);
+        }
+
+        let origin = match raw.split_once("://") {
+            Some((scheme, rest)) if is_scheme(scheme) => {
+                let end = rest.find(['/', '?']).unwrap_or(rest.len());
+                if end == 0 {
+                    return Err(HttpError::BadTarget);
+                }
+                target.form = TargetForm::Absolute;
+                target.scheme = Some(scheme.to_ascii_lowercase());
+                target.authority = Some(String::from(&rest[..end]));
+                &rest[end..]
+            }
+            _ if raw.starts_with('/') => raw,
+            _ => return Err(HttpError::BadTarget),
+        };
+
+        let (path, query) = match origin.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (origin, None),
+        };
+        target.path = match path {
+            "" => String::from("/"),
+            _ => percent_decode(path, false),
+        };
+        if let Some(query) = query {
+            target.query = parse_query(query);
+        }
+
+        return Ok(target);
+    }
+
+    // The path and query as they were received, in origin form. This is what
+    // an absolute-form target is forwarded as.
+    pub fn origin(&self) -> String {
+        match self.form {
+            TargetForm::Origin => self.raw.clone(),
+            TargetForm::Absolute => {
+                let rest = self.raw.split_once("://").map(|(_, rest)| rest).unwrap_or_default();
+                match rest.find(['/', '?']) {
+                    Some(i) if rest[i..].starts_with('/') => String::from(&rest[i..]),
+                    Some(i) => format!("/{}", &rest[i..]),
+                    None => String::from("/"),
+                }
+            }
+            TargetForm::Authority | TargetForm::Asterisk => self.raw.clone(),
+        }
+    }
+
+    // The first value of a query parameter.
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.query.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    // Every value of a repeated query parameter.
+    pub fn params<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> {
+        self.query.iter().filter(move |(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+fn is_scheme(scheme: &str) -> bool {
+    let mut bytes = scheme.bytes();
+    bytes.next().is_some_and(|b| b.is_ascii_alphabetic())
+        && bytes.all(|b| b.is_ascii_alphanumeric() || b == b'+' || b == b'-' || b == b'.')
+}
+
+// Splits a query string into decoded key value pairs. A '+' is a space and
+// keys without '=' get an empty value.
+pub fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key, true), percent_decode(value, true))
+        })
+        .collect()
+}
+
+// Drops the query parameters with these (decoded) names from a target, the
+// rest of the target is left as it is.
+pub fn remove_params(target: &str, names: &[String]) -> String {
+    let (path, query) = match target.split_once('?') {
+        Some(split) => split,
+        None => return String::from(target),
+    };
+
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split_once('=').map(|(k, _)| k).unwrap_or(pair);
+            !names.iter().any(|n| *n == percent_decode(key, true))
+        })
+        .collect();
+
+    if kept.is_empty() {
+        return String::from(path);
+    }
+    return format!("{}?{}", path, kept.join("&"));
+}
+
+// Decodes %XX escapes, invalid escapes are kept as they are. Bytes that do not
+// form UTF-8 are replaced.
+pub fn percent_decode(text: &str, plus_is_space: bool) -> String {
+    let bytes = text.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() && hex(bytes[i + 1]).is_some() && hex(bytes[i + 2]).is_some() => {
+                out.push(hex(bytes[i + 1]).unwrap() << 4 | hex(bytes[i + 2]).unwrap());
+                i += 3;
+            }
+            b'+' if plus_is_space => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    return String::from_utf8_lossy(&out).into_owned();
+}
+
+fn hex(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn origin_form() {
+        let t = RequestTarget::parse("/a%20b/c?x=1&y=a+b&x=2&flag&e=%zz", &HttpMethod::Get).unwrap();
+        assert_eq!(t.form, TargetForm::Origin);
+        assert_eq!(t.path, "/a b/c");
+        assert_eq!(t.param("x"), Some("1"));
+        assert_eq!(t.params("x").collect::<Vec<_>>(), vec!["1", "2"]);
+        assert_eq!(t.param("y"), Some("a b"));
+        assert_eq!(t.param("flag"), Some(""));
+        assert_eq!(t.param("e"), Some("%zz"));
+        assert_eq!(t.origin(), t.raw);
+    }
+
+    #[test]
+    fn absolute_form() {
+        let t = RequestTarget::parse("HTTP://example.com:8080/users/1?full=1", &HttpMethod::Get).unwrap();
+        assert_eq!(t.form, TargetForm::Absolute);
+        assert_eq!(t.scheme.as_deref(), Some("http"));
+        assert_eq!(t.authority.as_deref(), Some("example.com:8080"));
+        assert_eq!(t.path, "/users/1");
+        assert_eq!(t.origin(), "/users/1?full=1");
+
+        let t = RequestTarget::parse("http://example.com?x=1", &HttpMethod::Get).unwrap();
+        assert_eq!(t.path, "/");
+        assert_eq!(t.origin(), "/?x=1");
+        assert_eq!(RequestTarget::parse("http://example.com", &HttpMethod::Get).unwrap().origin(), "/");
+
+        assert!(RequestTarget::parse("http:///x", &HttpMethod::Get).is_err());
+    }
+
+    #[test]
+    fn authority_and_asterisk() {
+        let t = RequestTarget::parse("example.com:443", &HttpMethod::Connect).unwrap();
+        assert_eq!(t.form, TargetForm::Authority);
+        assert_eq!(t.authority.as_deref(), Some("example.com:443"));
+        assert!(RequestTarget::parse("example.com", &HttpMethod::Connect).is_err());
+        assert!(RequestTarget::parse("/x", &HttpMethod::Connect).is_err());
+
+        let t = RequestTarget::parse("*", &HttpMethod::Options).unwrap();
+        assert_eq!(t.form, TargetForm::Asterisk);
+        assert!(RequestTarget::parse("*", &HttpMethod::Get).is_err());
+        assert!(RequestTarget::parse("users", &HttpMethod::Get).is_err());
+    }
+
+    #[test]
+    fn removing_params() {
+        let names = vec![String::from("_"), String::from("cache buster")];
+        assert_eq!(remove_params("/a?_=123&b=1&cache+buster=x", &names), "/a?b=1");
+        assert_eq!(remove_params("/a?_=123", &names), "/a");
+        assert_eq!(remove_params("/a", &names), "/a");
+    }
+}
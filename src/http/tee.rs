@@ -0,0 +1,155 @@
+use std::ops::Range;
+
+use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+// Length and SHA-256 of a body, what is left to compare of a body that was
+// too large to keep.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BodyDigest {
+    pub length: usize,
+    pub sha256: String,
+}
+
+impl BodyDigest {
+    pub fn of(content: &[u8]) -> BodyDigest {
+        return BodyDigest {
+            length: content.len(),
+            sha256: hex(&Sha256::digest(content)),
+        };
+    }
+}
+
+impl std::fmt::Display for BodyDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes, sha256 {}", self.length, self.sha256)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// The copy of a streamed message that is kept for the comparison and the
+// store. At most `limit` bytes of the message are kept. When the message is
+// larger the rest is only hashed: the kept bytes are then just a prefix of
+// the message and the body is compared by its digest.
+pub struct Tee {
+    limit: usize,
+    // The bytes as they were written with the body content in them, only
+    // copied into one buffer at the end when there is more than one piece.
+    pieces: Vec<(Bytes, Vec<Range<usize>>)>,
+    kept: usize,
+    length: usize,
+    // Set once the limit is passed.
+    hasher: Option<Sha256>,
+}
+
+impl Tee {
+    pub fn new(limit: usize) -> Tee {
+        Tee {
+            limit,
+            pieces: Vec::new(),
+            kept: 0,
+            length: 0,
+            hasher: None,
+        }
+    }
+
+    // Adds the next bytes of the message, content are the ranges of the body
+    // content in them as found by the parser.
+    pub fn write(&mut self, bytes: Bytes, content: Vec<Range<usize>>) {
+        self.length += content.iter().map(|r| r.len()).sum::<usize>();
+
+        if let Some(hasher) = self.hasher.as_mut() {
+            for range in content.into_iter() {
+                hasher.update(&bytes[range]);
+            }
+            return;
+        }
+
+        if bytes.len() <= self.limit - self.kept {
+            self.kept += bytes.len();
+            self.pieces.push((bytes, content));
+            return;
+        }
+
+        // Over the limit: the content so far is hashed and from now on only
+        // the hash is updated.
+        let mut hasher = Sha256::new();
+        for (piece, ranges) in self.pieces.iter_mut() {
+            for range in ranges.drain(..) {
+                hasher.update(&piece[range]);
+            }
+        }
+        for range in content.into_iter() {
+            hasher.update(&bytes[range]);
+        }
+        let room = self.limit - self.kept;
+        self.kept += room;
+        self.pieces.push((bytes.slice(..room), Vec::new()));
+        self.hasher = Some(hasher);
+    }
+
+    // The kept bytes, and the digest of the body when not all of it was
+    // kept.
+    pub fn finish(mut self) -> (Bytes, Option<BodyDigest>) {
+        let digest = self.hasher.map(|hasher| BodyDigest {
+            length: self.length,
+            sha256: hex(&hasher.finalize()),
+        });
+        if self.pieces.len() == 1 {
+            return (self.pieces.remove(0).0, digest);
+        }
+        let mut bytes = BytesMut::with_capacity(self.kept);
+        for (piece, _) in self.pieces.iter() {
+            bytes.extend_from_slice(piece);
+        }
+        return (bytes.freeze(), digest);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn single_piece_is_not_copied() {
+        let message = Bytes::from("HTTP/1.1 200 OK\r\n\r\n");
+        let mut tee = Tee::new(64);
+        tee.write(message.clone(), Vec::new());
+        assert_eq!(tee.finish().0.as_ptr(), message.as_ptr());
+    }
+
+    #[test]
+    fn keeps_small_messages() {
+        let mut tee = Tee::new(64);
+        tee.write(Bytes::from("HTTP/1.1 200 OK\r\n\r\nab"), vec![19..21]);
+        tee.write(Bytes::from("cd"), vec![0..2]);
+        let (bytes, digest) = tee.finish();
+        assert_eq!(bytes, "HTTP/1.1 200 OK\r\n\r\nabcd");
+        assert_eq!(digest, None);
+    }
+
+    #[test]
+    fn hashes_past_the_limit() {
+        let mut tee = Tee::new(22);
+        tee.write(Bytes::from("HTTP/1.1 200 OK\r\n\r\nab"), vec![19..21]);
+        tee.write(Bytes::from("\r\ncd"), vec![2..4]);
+        tee.write(Bytes::from("ef"), vec![0..2]);
+        let (bytes, digest) = tee.finish();
+        assert_eq!(bytes, "HTTP/1.1 200 OK\r\n\r\nab\r");
+        assert_eq!(digest, Some(BodyDigest::of(b"abcdef")));
+        assert_eq!(digest.unwrap().length, 6);
+    }
+
+    #[test]
+    fn digest() {
+        assert_eq!(
+            BodyDigest::of(b"abc").to_string(),
+            "3 bytes, sha256 ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::breaker::BreakerState;
+use crate::compare::Outcome;
+use crate::http::error::ServerError;
+
+// Upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    // Counts per bucket, not cumulative. The last entry is +Inf.
+    counts: [u64; BUCKETS.len() + 1],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: Duration) {
+        let seconds = value.as_secs_f64();
+        let bucket = BUCKETS.iter().position(|&le| seconds <= le).unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+// Recent results of the requests sent to an upstream.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Health {
+    pub successes: u64,
+    pub failures: u64,
+    pub consecutive_failures: u64,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+// (route, route template, shadow, outcome)
+type ComparisonKey = (String, String, String, &'static str);
+
+// Counters of the proxy, rendered in the Prometheus text format by the admin
+// listener.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // (method, route, status)
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    // By route.
+    main_latency: Mutex<BTreeMap<String, Histogram>>,
+    // By shadow name.
+    shadow_latency: Mutex<BTreeMap<String, Histogram>>,
+    comparisons: Mutex<BTreeMap<ComparisonKey, u64>>,
+    // (shadow, reason)
+    dropped: Mutex<BTreeMap<(String, &'static str), u64>>,
+    // By reason.
+    rejected: Mutex<BTreeMap<&'static str, u64>>,
+    // Exchanges waiting in the channel to the parsing runtime.
+    queue_depth: AtomicI64,
+    // By upstream address.
+    upstreams: Mutex<BTreeMap<String, Health>>,
+}
+
+impl Metrics {
+    pub fn request(&self, method: &str, route: &str, status: u16) {
+        let key = (String::from(method), String::from(route), status);
+        *self.requests.lock().unwrap().entry(key).or_default() += 1;
+    }
+
+    pub fn main_latency(&self, route: &str, latency: Duration) {
+        let mut histograms = self.main_latency.lock().unwrap();
+        histograms.entry(String::from(route)).or_default().observe(latency);
+    }
+
+    pub fn shadow_latency(&self, shadow: &str, latency: Duration) {
+        let mut histograms = self.shadow_latency.lock().unwrap();
+        histograms.entry(String::from(shadow)).or_default().observe(latency);
+    }
+
+    pub fn comparison(&self, route: &str, template: &str, shadow: &str, outcome: Outcome) {
+        let outcome = match outcome {
+            Outcome::Match => "match",
+            Outcome::Mismatch => "mismatch",
+            Outcome::Error => "error",
+        };
+        let key = (String::from(route), String::from(template), String::from(shadow), outcome);
+        *self.comparisons.lock().unwrap().entry(key).or_default() += 1;
+    }
+
+    // An exchange that was not mirrored to the shadow, e.g. because its
+    // circuit breaker is open.
+    pub fn dropped(&self, shadow: &str, reason: &'static str) {
+        let key = (String::from(shadow), reason);
+        *self.dropped.lock().unwrap().entry(key).or_default() += 1;
+    }
+
+    // A client request that was not forwarded because it was malformed or
+    // over one of the limits.
+    pub fn rejected(&self, reason: &'static str) {
+        *self.rejected.lock().unwrap().entry(reason).or_default() += 1;
+    }
+
+    // Records the result of a request to main or a shadow.
+    pub fn upstream(&self, address: &str, error: Option<&ServerError>) {
+        let mut upstreams = self.upstreams.lock().unwrap();
+        let health = upstreams.entry(String::from(address)).or_default();
+        match error {
+            None => {
+                health.successes += 1;
+                health.consecutive_failures = 0;
+                health.last_success = Some(Utc::now());
+            }
+            Some(e) => {
+                health.failures += 1;
+                health.consecutive_failures += 1;
+                health.last_failure = Some(Utc::now());
+                health.last_error = Some(e.to_string());
+            }
+        }
+    }
+
+    pub fn health(&self, address: &str) -> Health {
+        return self.upstreams.lock().unwrap().get(address).cloned().unwrap_or_default();
+    }
+
+    pub fn queued(&self) {
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dequeued(&self) {
+        self.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    // The metrics in the Prometheus text exposition format. The breaker
+    // states are passed in since they live with the proxy.
+    pub fn render(&self, breakers: &[(&str, BreakerState)]) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "shadowapi_requests_total", "counter", "Requests handled by the proxy.");
+        for ((method, route, status), n) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "shadowapi_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                escape(method),
+                escape(route),
+                status,
+                n
+            );
+        }
+
+        header(&mut out, "shadowapi_main_latency_seconds", "histogram", "Latency of main responses.");
+        for (route, histogram) in self.main_latency.lock().unwrap().iter() {
+            histogram_lines(&mut out, "shadowapi_main_latency_seconds", "route", route, histogram);
+        }
+
+        header(&mut out, "shadowapi_shadow_latency_seconds", "histogram", "Latency of shadow responses.");
+        for (shadow, histogram) in self.shadow_latency.lock().unwrap().iter() {
+            histogram_lines(&mut out, "shadowapi_shadow_latency_seconds", "shadow", shadow, histogram);
+        }
+
+        let comparisons = self.comparisons.lock().unwrap().clone();
+        header(&mut out, "shadowapi_comparisons_total", "counter", "Compared exchanges by outcome.");
+        for ((route, template, shadow, outcome), n) in comparisons.iter() {
+            let _ = writeln!(
+                out,
+                "shadowapi_comparisons_total{{route=\"{}\",template=\"{}\",shadow=\"{}\",outcome=\"{}\"}} {}",
+                escape(route),
+                escape(template),
+                escape(shadow),
+                outcome,
+                n
+            );
+        }
+
+        let mut totals: BTreeMap<(&str, &str, &str), u64> = BTreeMap::new();
+        for ((route, template, shadow, _), n) in comparisons.iter() {
+            *totals.entry((route, template, shadow)).or_default() += n;
+        }
+        header(&mut out, "shadowapi_outcome_ratio", "gauge", "Fraction of the compared exchanges per outcome.");
+        for ((route, template, shadow, outcome), n) in comparisons.iter() {
+            let total = totals[&(route.as_str(), template.as_str(), shadow.as_str())];
+            let _ = writeln!(
+                out,
+                "shadowapi_outcome_ratio{{route=\"{}\",template=\"{}\",shadow=\"{}\",outcome=\"{}\"}} {}",
+                escape(route),
+                escape(template),
+                escape(shadow),
+                outcome,
+                *n as f64 / total as f64
+            );
+        }
+
+        header(&mut out, "shadowapi_dropped_exchanges_total", "counter", "Exchanges that were not mirrored to a shadow.");
+        for ((shadow, reason), n) in self.dropped.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "shadowapi_dropped_exchanges_total{{shadow=\"{}\",reason=\"{}\"}} {}",
+                escape(shadow),
+                reason,
+                n
+            );
+        }
+
+        header(&mut out, "shadowapi_rejected_requests_total", "counter", "Client requests that were rejected before forwarding.");
+        for (reason, n) in self.rejected.lock().unwrap().iter() {
+            let _ = writeln!(out, "shadowapi_rejected_requests_total{{reason=\"{}\"}} {}", reason, n);
+        }
+
+        header(&mut out, "shadowapi_queue_depth", "gauge", "Exchanges waiting to be mirrored.");
+        let _ = writeln!(out, "shadowapi_queue_depth {}", self.queue_depth.load(Ordering::Relaxed).max(0));
+
+        header(
+            &mut out,
+            "shadowapi_circuit_breaker_state",
+            "gauge",
+            "Circuit breaker state per shadow: 0 closed, 1 open, 2 half open.",
+        );
+        for (shadow, state) in breakers {
+            let value = match state {
+                BreakerState::Closed => 0,
+                BreakerState::Open => 1,
+                BreakerState::HalfOpen => 2,
+            };
+            let _ = writeln!(out, "shadowapi_circuit_breaker_state{{shadow=\"{}\"}} {}", escape(shadow), value);
+        }
+
+        return out;
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn histogram_lines(out: &mut String, name: &str, label: &str, value: &str, histogram: &Histogram) {
+    let value = escape(value);
+    let mut cumulative = 0;
+    for (i, count) in histogram.counts.iter().enumerate() {
+        cumulative += count;
+        let le = match BUCKETS.get(i) {
+            Some(le) => le.to_string(),
+            None => String::from("+Inf"),
+        };
+        let _ = writeln!(out, "{}_bucket{{{}=\"{}\",le=\"{}\"}} {}", name, label, value, le, cumulative);
+    }
+    let _ = writeln!(out, "{}_sum{{{}=\"{}\"}} {}", name, label, value, histogram.sum);
+    let _ = writeln!(out, "{}_count{{{}=\"{}\"}} {}", name, label, value, histogram.count);
+}
+
+// Label values escape backslashes, quotes and newlines.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::default();
+        metrics.request("GET", "users", 200);
+        metrics.request("GET", "users", 200);
+        metrics.main_latency("users", Duration::from_millis(20));
+        metrics.comparison("users", "/users/{id}", "a", Outcome::Match);
+        metrics.comparison("users", "/users/{id}", "a", Outcome::Match);
+        metrics.comparison("users", "/users/{id}", "a", Outcome::Mismatch);
+        metrics.comparison("users", "/users/{id}", "a", Outcome::Error);
+        metrics.dropped("a", "circuit_open");
+        metrics.rejected("headers_too_large");
+        metrics.queued();
+
+        let text = metrics.render(&[("a", BreakerState::Open)]);
+        let has = |line: &str| text.lines().any(|l| l == line);
+
+        assert!(has("shadowapi_requests_total{method=\"GET\",route=\"users\",status=\"200\"} 2"));
+        assert!(has("shadowapi_main_latency_seconds_bucket{route=\"users\",le=\"0.01\"} 0"));
+        assert!(has("shadowapi_main_latency_seconds_bucket{route=\"users\",le=\"0.025\"} 1"));
+        assert!(has("shadowapi_main_latency_seconds_bucket{route=\"users\",le=\"+Inf\"} 1"));
+        assert!(has("shadowapi_main_latency_seconds_count{route=\"users\"} 1"));
+        assert!(has("shadowapi_outcome_ratio{route=\"users\",template=\"/users/{id}\",shadow=\"a\",outcome=\"match\"} 0.5"));
+        assert!(has("shadowapi_dropped_exchanges_total{shadow=\"a\",reason=\"circuit_open\"} 1"));
+        assert!(has("shadowapi_rejected_requests_total{reason=\"headers_too_large\"} 1"));
+        assert!(has("shadowapi_queue_depth 1"));
+        assert!(has("shadowapi_circuit_breaker_state{shadow=\"a\"} 1"));
+    }
+
+    #[test]
+    fn escape_labels() {
+        assert_eq!(escape("a\"b\\c\n"), "a\\\"b\\\\c\\n");
+    }
+}
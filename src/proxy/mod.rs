@@ -0,0 +1,1060 @@
+use std::io::IoSlice;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, Bytes};
+use chrono::Utc;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    runtime::Runtime,
+    signal::unix::{signal, SignalKind},
+    sync::Notify,
+};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+
+use crate::admin;
+use crate::breaker::CircuitBreaker;
+use crate::canary::Canary;
+use crate::capture::{CaptureRecord, CaptureWriter};
+use crate::compare::{self, ComparisonRecord};
+use crate::config::{CompareConfig, Config, Mode, ShadowConfig};
+use crate::http::error::{HttpError, ServerError};
+use crate::http::parser::{MessageParser, ParseState};
+use crate::http::partials::HttpMethod;
+use crate::http::tee::Tee;
+use crate::http::{request::RawHttpRequest, response::RawHttpResponse};
+use crate::metrics::Metrics;
+use crate::rewrite::{self, Rewriter};
+use crate::routing::Router;
+use crate::store::ResultStore;
+use crate::util::log;
+
+// Size of the reads from clients and upstreams.
+const BUFSIZE: usize = 1500;
+
+// State shared by the connection handlers and the parsing runtime.
+pub struct Proxy {
+    pub config: Config,
+    pub router: Router,
+    pub canary: Option<Canary>,
+    // Rewrite rules per shadow, in the order of Config::shadows.
+    pub rewriters: Vec<Rewriter>,
+    // Circuit breakers per shadow, in the order of Config::shadows.
+    pub breakers: Vec<CircuitBreaker>,
+    pub metrics: Metrics,
+    // Runtime state changed through the admin listener. Sampling rates are
+    // stored as f64 bits, both they and the comparison rules are per shadow.
+    paused: AtomicBool,
+    sampling: Vec<AtomicU64>,
+    rules: RwLock<Vec<Arc<CompareConfig>>>,
+    draining: AtomicBool,
+    drain: Notify,
+    // Client connections that are being handled.
+    pub in_flight: AtomicUsize,
+}
+
+// An exchange with main, handed from the connection handler to the parsing
+// runtime which mirrors it to the shadows.
+pub struct Exchange {
+    // Correlation ID, also sent upstream in the X-Request-ID header.
+    pub id: String,
+    // Name of the matched route.
+    pub route: String,
+    // The request target as a route template like /users/{id}.
+    pub template: String,
+    pub request: RawHttpRequest,
+    pub main: RawHttpResponse,
+    // Indices of the shadows the request is routed to.
+    pub shadows: Vec<usize>,
+    // Set when the canary shadow was already called while handling the
+    // client, it is not called a second time.
+    pub canary: Option<CanaryExchange>,
+}
+
+pub struct CanaryExchange {
+    pub shadow: usize,
+    pub response: Result<RawHttpResponse, ServerError>,
+    // Whether the client got the shadow response.
+    pub served: bool,
+}
+
+impl Proxy {
+    pub fn new(config: Config) -> Result<Proxy, std::io::Error> {
+        let invalid = |e: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, e);
+
+        let router = Router::new(&config).map_err(invalid)?;
+        let canary = match &config.canary {
+            Some(c) => Some(Canary::new(c, &config).map_err(invalid)?),
+            None => None,
+        };
+
+        let rewriters = rewrite::rewriters(&config).map_err(invalid)?;
+        let breakers = config.shadows.iter().map(|s| CircuitBreaker::new(&s.breaker)).collect();
+        let sampling = config.shadows.iter().map(|s| AtomicU64::new(s.sampling.to_bits())).collect();
+        let rules = config.shadows.iter().map(|s| Arc::new(s.rules(&config).clone())).collect();
+
+        Ok(Proxy {
+            config,
+            router,
+            canary,
+            rewriters,
+            breakers,
+            metrics: Metrics::default(),
+            paused: AtomicBool::new(false),
+            sampling,
+            rules: RwLock::new(rules),
+            draining: AtomicBool::new(false),
+            drain: Notify::new(),
+            in_flight: AtomicUsize::new(0),
+        })
+    }
+
+    // While paused, requests are only proxied to main. Captures are still
+    // written.
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn sampling(&self, shadow: usize) -> f64 {
+        f64::from_bits(self.sampling[shadow].load(Ordering::Relaxed))
+    }
+
+    // The sampling rate of a shadow for the requests with this route
+    // template.
+    pub fn template_sampling(&self, shadow: usize, template: &str) -> f64 {
+        match self.config.shadows[shadow].template_sampling.get(template) {
+            Some(&rate) => rate,
+            None => self.sampling(shadow),
+        }
+    }
+
+    pub fn set_sampling(&self, shadow: usize, rate: f64) {
+        self.sampling[shadow].store(rate.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn rules(&self, shadow: usize) -> Arc<CompareConfig> {
+        self.rules.read().unwrap()[shadow].clone()
+    }
+
+    // Time a client gets to take each write of its response.
+    pub fn write_timeout(&self) -> Duration {
+        return Duration::from_millis(self.config.client_timeouts.write_ms);
+    }
+
+    // Replaces the comparison rules of every shadow, in the order of
+    // Config::shadows.
+    pub fn set_rules(&self, rules: Vec<CompareConfig>) {
+        *self.rules.write().unwrap() = rules.into_iter().map(Arc::new).collect();
+    }
+
+    pub fn draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    // Stops accepting client connections. Connections in flight are still
+    // handled and their exchanges mirrored.
+    pub fn drain(&self) {
+        if !self.draining.swap(true, Ordering::Relaxed) {
+            self.drain.notify_one();
+        }
+    }
+}
+
+pub fn run(config: Config) -> Result<(), std::io::Error> {
+    let proxy = Arc::new(Proxy::new(config)?);
+    let config = &proxy.config;
+
+    // TODO: configure the amount of main and comparison threads with external
+    // configuration (JSON/cli/...)
+    let main_rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4) /* use 10 threads for handling connections */
+        .enable_io()
+        .enable_time() /* canary shadow timeouts */
+        .build()?;
+
+    let parsing_rt: Runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_io()
+        .enable_time() /* shadow timeouts */
+        .build()?;
+
+    let store_rt: Runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_io()
+        .build()?;
+
+    let store = ResultStore::open(&config.store, config.samples_per_cluster)?;
+
+    let capture = match &config.capture {
+        Some(path) => Some(CaptureWriter::open(path)?),
+        None => None,
+    };
+
+    if config.mode == Mode::Capture && capture.is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "capture mode needs a capture file",
+        ));
+    }
+
+    let (store_tx, store_rx) = tokio::sync::mpsc::channel::<ComparisonRecord>(1_000);
+
+    let stored = store_rt.spawn(store.run(store_rx));
+
+    // The capture file is written by the store runtime too.
+    let (capture_tx, captured) = match capture {
+        Some(writer) => {
+            let (capture_tx, capture_rx) = tokio::sync::mpsc::channel::<CaptureRecord>(1_000);
+            (Some(capture_tx), Some(store_rt.spawn(writer.run(capture_rx))))
+        }
+        None => (None, None),
+    };
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Exchange>(1_000);
+
+    let parsing_proxy = proxy.clone();
+
+    parsing_rt.spawn(async move {
+        loop {
+            let v = rx.recv().await;
+
+            // Every sender is gone once the proxy shuts down. The shadow
+            // requests in flight finish on their own and the store stops
+            // after the last of them.
+            if let None = v {
+                debug!("no more exchanges to mirror");
+                break;
+            }
+
+            let mut exchange = v.unwrap();
+            parsing_proxy.metrics.dequeued();
+            let span = info_span!("exchange", id = %exchange.id);
+
+            // Messages larger than the tee limit were streamed and only the
+            // start of them was kept. They can not be replayed from the
+            // capture file, and the shadows can not get such a request.
+            let request_kept = exchange.request.digest.is_none();
+            if !request_kept || exchange.main.digest.is_some() {
+                span.in_scope(|| debug!("exchange larger than the tee limit, not captured"));
+            } else if let Some(capture_tx) = capture_tx.as_ref() {
+                let record = CaptureRecord {
+                    timestamp: Utc::now(),
+                    request: exchange.request.clone(),
+                    response: Some(exchange.main.clone()),
+                };
+                // Parsing does not wait for the disk, when the writer falls
+                // behind this far the exchange is not captured.
+                if let Err(e) = capture_tx.try_send(record) {
+                    span.in_scope(|| error!("exchange not captured: {}", e));
+                }
+            }
+
+            // In capture mode the shadow is compared later from the capture
+            // file, see the compare subcommand.
+            if parsing_proxy.config.mode == Mode::Capture || parsing_proxy.paused() {
+                continue;
+            }
+
+            for &index in exchange.shadows.iter() {
+                let canary = exchange.canary.take_if(|c| c.shadow == index);
+
+                let shadow = &parsing_proxy.config.shadows[index];
+                if !request_kept {
+                    parsing_proxy.metrics.dropped(&shadow.name, "body_too_large");
+                    continue;
+                }
+                if canary.is_none() && !sampled(parsing_proxy.template_sampling(index, &exchange.template)) {
+                    continue;
+                }
+                if canary.is_none() && !parsing_proxy.breakers[index].allow() {
+                    parsing_proxy.metrics.dropped(&shadow.name, "circuit_open");
+                    continue;
+                }
+
+                let proxy = parsing_proxy.clone();
+                let store_tx = store_tx.clone();
+                let raw_request = exchange.request.clone();
+                let main_response = exchange.main.clone();
+                let route = exchange.route.clone();
+                let span = info_span!(parent: &span, "shadow", name = %shadow.name);
+
+                tokio::spawn(async move {
+                    let config = &proxy.config;
+                    let shadow = &config.shadows[index];
+                    // The breaker already has the outcome of a canary request.
+                    let (shadow_response, served) = match canary {
+                        Some(c) => (c.response, c.served),
+                        None => {
+                            let limit = config.tee_limit;
+                            let response = request_shadow(shadow, &proxy.rewriters[index], &raw_request, limit).await;
+                            proxy.breakers[index].record(response.is_ok());
+                            (response, false)
+                        }
+                    };
+
+                    proxy.metrics.upstream(&shadow.address, shadow_response.as_ref().err());
+                    if let Some(latency) = shadow_response.as_ref().ok().and_then(|r| r.latency) {
+                        proxy.metrics.shadow_latency(&shadow.name, latency);
+                    }
+
+                    let mut record = compare::evaluate(
+                        shadow.name.as_str(),
+                        &route,
+                        proxy.router.templates(),
+                        raw_request,
+                        main_response,
+                        shadow_response,
+                        &proxy.rules(index),
+                    );
+                    record.canary = served;
+                    proxy.metrics.comparison(&route, &record.template(), &shadow.name, record.outcome);
+
+                    if let Some(e) = &record.error {
+                        warn!(target: log::SHADOW, "{}", e);
+                    } else {
+                        debug!(target: log::SHADOW, outcome = ?record.outcome, "compared");
+                    }
+
+                    let _ = store_tx.send(record).await;
+                }.instrument(span));
+            }
+        }
+    });
+
+    if let Some(admin) = &config.admin {
+        let listener = main_rt.block_on(TcpListener::bind(admin.address.as_str()))?;
+        main_rt.spawn(admin::serve(listener, proxy.clone()));
+    }
+
+    let deadline = main_rt.block_on(async {
+        let listener = TcpListener::bind(config.proxy.as_str());
+        let listener = listener.await.expect("proxy is not available");
+        let mut terminated = std::pin::pin!(terminated());
+        let mut terminating = false;
+
+        loop {
+            let (tcpstream, addr) = tokio::select! {
+                accepted = listener.accept() => accepted.expect("could not accept incoming tcp stream"),
+                _ = proxy.drain.notified() => break,
+                _ = &mut terminated => {
+                    terminating = true;
+                    proxy.drain();
+                    break;
+                }
+            };
+
+            let ltx = tx.clone();
+
+            let proxy = proxy.clone();
+
+            main_rt.spawn(async move {
+                let _in_flight = InFlight::new(&proxy.in_flight);
+                let result = handle_connection(tcpstream, &proxy).await;
+
+                if let Err(e) = result {
+                    warn!("issue in main server: {}", e);
+                    return;
+                }
+
+                let exchange = match result.unwrap() {
+                    Some(exchange) => exchange,
+                    None => return, /* rejected by the router */
+                };
+
+                let id = exchange.id.clone();
+                proxy.metrics.queued();
+                let sent = ltx.send(exchange).await;
+
+                if let Err(tokio::sync::mpsc::error::SendError(exchange)) = sent {
+                    error!(id = %id, "receiver dropped");
+                    proxy.metrics.dequeued();
+                    for &index in exchange.shadows.iter() {
+                        proxy.metrics.dropped(&proxy.config.shadows[index].name, "channel_closed");
+                    }
+                    // NOTE: the send method can return SendError which holds
+                    // the T that was sent but failed. Send blocks if there
+                    // is no capacity, so the receiver has probably been
+                    // dropped. I don't think the I can restart the receiver in
+                    // an ergonomic way here.
+                }
+
+                debug!(id = %id, "client handled: {}", addr);
+            });
+
+            debug!("client connected: {}", addr);
+        }
+
+        // Closing the listener makes new connections fail, the admin
+        // listener and the requests in flight keep running.
+        drop(listener);
+        info!("draining, no longer accepting connections");
+        if !terminating {
+            terminated.await;
+        }
+
+        let deadline = tokio::time::Instant::now() + Duration::from_millis(proxy.config.shutdown_timeout_ms);
+        info!(in_flight = proxy.in_flight.load(Ordering::Relaxed), "shutting down");
+        while proxy.in_flight.load(Ordering::Relaxed) > 0 && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        return deadline;
+    });
+
+    // Without senders the parsing runtime stops once the queue is empty, the
+    // capture writer once the last exchange is captured and the store once the
+    // last comparison is written.
+    drop(tx);
+    let flushed = main_rt.block_on(async {
+        tokio::time::timeout_at(deadline, async {
+            let _ = stored.await;
+            if let Some(captured) = captured {
+                let _ = captured.await;
+            }
+        })
+        .await
+    });
+    match flushed {
+        Ok(_) => info!("shut down"),
+        Err(_) => warn!(
+            in_flight = proxy.in_flight.load(Ordering::Relaxed),
+            "shutdown deadline passed, exchanges that were not stored yet are lost"
+        ),
+    }
+
+    // Whatever is still running is cancelled.
+    main_rt.shutdown_background();
+    parsing_rt.shutdown_background();
+    store_rt.shutdown_background();
+
+    Ok(())
+}
+
+// Resolves on SIGTERM or Ctrl-C.
+async fn terminated() {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(e) => {
+            error!("can not listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    tokio::select! {
+        _ = sigterm.recv() => info!("received SIGTERM"),
+        _ = tokio::signal::ctrl_c() => info!("received Ctrl-C"),
+    }
+}
+
+// Answers a request that is not forwarded because it is malformed or over one
+// of the limits, and closes the connection.
+async fn reject(client: &mut TcpStream, proxy: &Proxy, method: &str, error: HttpError) {
+    let (reason, status, response) = match error {
+        HttpError::RequestLineTooLong => ("request_line_too_long", 414, "HTTP/1.1 414 URI Too Long"),
+        HttpError::TooManyHeaders => ("too_many_headers", 431, "HTTP/1.1 431 Request Header Fields Too Large"),
+        HttpError::HeadersTooLarge => ("headers_too_large", 431, "HTTP/1.1 431 Request Header Fields Too Large"),
+        HttpError::BodyTooLarge => ("body_too_large", 413, "HTTP/1.1 413 Content Too Large"),
+        HttpError::Timeout => ("timeout", 408, "HTTP/1.1 408 Request Timeout"),
+        _ => ("bad_request", 400, "HTTP/1.1 400 Bad Request"),
+    };
+    info!("request from client rejected: {}", error);
+    proxy.metrics.rejected(reason);
+    proxy.metrics.request(method, "rejected", status);
+    let response = format!("{}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", response);
+    let _ = write_client(client, response.as_bytes(), proxy.write_timeout()).await;
+    let _ = client.shutdown().await;
+}
+
+// Writes to the client, which fails with TimedOut when the client does not
+// take the bytes within the timeout.
+async fn write_client(client: &mut TcpStream, bytes: &[u8], timeout: Duration) -> Result<(), std::io::Error> {
+    match tokio::time::timeout(timeout, client.write_all(bytes)).await {
+        Ok(result) => result,
+        Err(_) => Err(std::io::Error::from(std::io::ErrorKind::TimedOut)),
+    }
+}
+
+// Forwards the client request to main (or the canary) and returns the
+// exchange, or None when the request did not match any route and was
+// rejected.
+async fn handle_connection(
+    mut client_stream: tokio::net::TcpStream,
+    proxy: &Proxy,
+) -> Result<Option<Exchange>, ServerError> {
+    let mut localbuf = [0u8; BUFSIZE];
+    let mut parser = MessageParser::request().with_limits(proxy.config.limits);
+
+    // Only the header section is read here, the body is streamed to main. The
+    // client gets first_byte_ms to start the request and header_ms to finish
+    // the header section once it started.
+    let timeouts = &proxy.config.client_timeouts;
+    let mut deadline = tokio::time::Instant::now() + Duration::from_millis(timeouts.first_byte_ms);
+    let mut started = false;
+    while !parser.has_head() {
+        let read = tokio::time::timeout_at(deadline, read_into(&client_stream, &mut parser, &mut localbuf)).await;
+        match read {
+            Err(_) if !started => {
+                debug!("client sent nothing, connection closed");
+                let _ = client_stream.shutdown().await;
+                return Ok(None);
+            }
+            Err(_) => {
+                reject(&mut client_stream, proxy, "UNKNOWN", HttpError::Timeout).await;
+                return Ok(None);
+            }
+            Ok(Ok(_)) if !started => {
+                started = true;
+                deadline = tokio::time::Instant::now() + Duration::from_millis(timeouts.header_ms);
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(ReadError::Io(e))) => {
+                return Err(ServerError::ServerReadError(String::from("client"), Box::new(e)));
+            }
+            Ok(Err(ReadError::Http(e))) => {
+                reject(&mut client_stream, proxy, "UNKNOWN", e).await;
+                return Ok(None);
+            }
+        }
+    }
+
+    let (bytes, content) = parser.drain();
+    let mut request = RawHttpRequest::from(bytes.clone());
+    request.normalize_target();
+    let id = request.ensure_request_id();
+    // The body moved along with the changes to the head.
+    let content = content
+        .into_iter()
+        .map(|r| r.start + request.bytes.len() - bytes.len()..r.end + request.bytes.len() - bytes.len())
+        .collect();
+    let span = info_span!("exchange", id = %id);
+
+    let body = Body { parser, content };
+    return respond(client_stream, request, body, id, proxy).instrument(span).await;
+}
+
+// What is known of the request body when the request is routed: the content
+// in the bytes read so far, and the parser that reads the rest.
+struct Body {
+    parser: MessageParser,
+    content: Vec<Range<usize>>,
+}
+
+// Answers the client once the request is read, inside the span of the
+// exchange.
+async fn respond(
+    mut client_stream: tokio::net::TcpStream,
+    request: RawHttpRequest,
+    mut body: Body,
+    id: String,
+    proxy: &Proxy,
+) -> Result<Option<Exchange>, ServerError> {
+    // The method label of the request metrics. Any token is a method, so the
+    // ones outside of the standard set share a label to keep the number of
+    // series bounded.
+    let line = request.request_line();
+    let method = match &line {
+        Ok((HttpMethod::Extension(_), _, _)) => String::from("OTHER"),
+        Ok((method, _, _)) => method.to_string(),
+        Err(_) => String::from("UNKNOWN"),
+    };
+    // The target is in origin-form already.
+    let template = match &line {
+        Ok((_, target, _)) => proxy.router.templates().normalize(target),
+        Err(_) => String::new(),
+    };
+
+    let target = match proxy.router.resolve(&request) {
+        Some(target) => target,
+        None => {
+            info!("request matches no route, rejected");
+            proxy.metrics.request(&method, "rejected", 404);
+            let response = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+            let _ = write_client(&mut client_stream, response, proxy.write_timeout()).await;
+            let _ = client_stream.shutdown().await;
+            return Ok(None);
+        }
+    };
+
+    // The canary needs the whole request, and the client can only be answered
+    // once both responses are complete.
+    let complete = body.parser.state() == ParseState::Done;
+    let canary = proxy
+        .canary
+        .as_ref()
+        .filter(|c| complete && target.shadows.contains(&c.shadow) && c.assign(&request))
+        .filter(|c| !proxy.paused() && proxy.breakers[c.shadow].allow());
+
+    // A canary request is sent to main and the canary shadow at the same
+    // time, the shadow answers the client unless it failed or differs from
+    // main in a critical way.
+    let (request, main_response, canary) = match canary {
+        Some(c) => {
+            let shadow = &proxy.config.shadows[c.shadow];
+            let (main_response, shadow_response) = tokio::join!(
+                request_server(target.main.as_str(), &request, usize::MAX),
+                request_shadow(shadow, &proxy.rewriters[c.shadow], &request, usize::MAX)
+            );
+            // Recorded here, allow() may have let this request through as the
+            // probe of a half open breaker, and the exchange may never be
+            // compared (capture mode, paused).
+            proxy.breakers[c.shadow].record(shadow_response.is_ok());
+
+            let served = match (&main_response, &shadow_response) {
+                (Ok(m), Ok(s)) => c.accept(m, s, &proxy.rules(c.shadow)),
+                _ => false,
+            };
+            if !served {
+                info!(target: log::SHADOW, shadow = %shadow.name, "canary falling back to main");
+            }
+
+            let canary = CanaryExchange {
+                shadow: c.shadow,
+                response: shadow_response,
+                served,
+            };
+            (request, main_response, Some(canary))
+        }
+        None => {
+            let streamed = stream_main(&target.main, request, &mut body, &mut client_stream, proxy).await;
+            match streamed {
+                Ok((request, response)) => (request, Ok(response), None),
+                Err(Streamed { error, answered: true, .. }) => {
+                    // Part of main's response was sent, the client can only be
+                    // disconnected.
+                    warn!("error with main after answering: {}", error);
+                    proxy.metrics.upstream(&target.main, Some(&error));
+                    let _ = client_stream.shutdown().await;
+                    return Err(error);
+                }
+                Err(Streamed { rejected: Some(e), .. }) => {
+                    // Main gets a cut off request and the connection to it
+                    // is already closed.
+                    reject(&mut client_stream, proxy, &method, e).await;
+                    return Ok(None);
+                }
+                Err(Streamed { error, answered: false, .. }) => (RawHttpRequest::default(), Err(error), None),
+            }
+        }
+    };
+
+    proxy.metrics.upstream(&target.main, main_response.as_ref().err());
+
+    if let Err(e) = main_response {
+        warn!("error with main: {}", e);
+        let (status, response) = match e {
+            ServerError::Unresponsive(_, _) => (503, "HTTP/1.1 503 Service Unavailable"),
+            ServerError::Timeout(_, _) => (504, "HTTP/1.1 504 Gateway Timeout"),
+            ServerError::ServerWriteError(_, _) | ServerError::ServerReadError(_, _) => {
+                (500, "HTTP/1.1 500 Internal Server Error")
+            }
+        };
+        proxy.metrics.request(&method, &target.route, status);
+        let _ = write_client(&mut client_stream, response.as_bytes(), proxy.write_timeout()).await;
+
+        return Err(e);
+    } else {
+        let response = match &canary {
+            Some(CanaryExchange { response: Ok(shadow_response), served: true, .. }) => shadow_response,
+            _ => main_response.as_ref().unwrap(),
+        };
+        proxy.metrics.request(&method, &target.route, response.status().code().unwrap_or(0));
+        if let Some(latency) = main_response.as_ref().unwrap().latency {
+            proxy.metrics.main_latency(&target.route, latency);
+        }
+        // Without a canary the response was already streamed to the client.
+        if canary.is_some() {
+            if let Err(e) = write_client(&mut client_stream, &response.bytes, proxy.write_timeout()).await {
+                warn!("error writing the response to the client: {}", e);
+            }
+        }
+    }
+
+    let _ = client_stream.shutdown().await;
+
+    // The request and main response are recorded to the capture file (when
+    // configured) by the parsing runtime before the shadow is called.
+
+    return Ok(Some(Exchange {
+        id,
+        route: target.route.clone(),
+        template,
+        request,
+        main: main_response.unwrap(),
+        shadows: target.shadows.clone(),
+        canary,
+    }));
+}
+
+// Counts a connection as in flight until dropped.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn new(counter: &'a AtomicUsize) -> InFlight<'a> {
+        counter.fetch_add(1, Ordering::Relaxed);
+        InFlight(counter)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn sampled(rate: f64) -> bool {
+    rate >= 1.0 || rand::random::<f64>() < rate
+}
+
+// Sends the shadow's rewritten copy of the request to the shadow, giving up
+// after the shadow's timeout.
+pub async fn request_shadow(
+    shadow: &ShadowConfig,
+    rewriter: &Rewriter,
+    request: &RawHttpRequest,
+    limit: usize,
+) -> Result<RawHttpResponse, ServerError> {
+    let rewritten = rewriter.apply(request);
+    let parts = [rewritten.head, rewritten.body];
+    let timeout = Duration::from_millis(shadow.timeout_ms);
+    match tokio::time::timeout(timeout, send(shadow.address.as_str(), &parts, &method(request), limit)).await {
+        Ok(response) => response,
+        Err(_) => Err(ServerError::Timeout(shadow.address.clone(), timeout)),
+    }
+}
+
+// Sends a request that is in memory and reads the response, keeping at most
+// `limit` bytes of it, see Tee.
+pub async fn request_server<T>(
+    target: T,
+    request: &RawHttpRequest,
+    limit: usize,
+) -> Result<RawHttpResponse, ServerError>
+where
+    T: Into<String>,
+{
+    return send(target, std::slice::from_ref(&request.bytes), &method(request), limit).await;
+}
+
+// Sends a request that is in memory in parts, e.g. a rewritten head and the
+// original body, and reads the response to it.
+async fn send<T>(
+    target: T,
+    parts: &[Bytes],
+    method: &HttpMethod,
+    limit: usize,
+) -> Result<RawHttpResponse, ServerError>
+where
+    T: Into<String>,
+{
+    let target: String = target.into();
+    let started = Instant::now();
+    let server = TcpStream::connect(target.clone()).await;
+
+    if let Err(e) = server {
+        return Err(ServerError::Unresponsive(target, Box::new(e)));
+    }
+
+    let mut server = server.unwrap();
+
+    let res = write_parts(&mut server, parts).await;
+
+    if let Err(e) = res {
+        return Err(ServerError::ServerWriteError(target, Box::new(e)));
+    }
+
+    let mut localbuf = [0u8; BUFSIZE];
+    let mut parser = MessageParser::response(method);
+    let mut tee = Tee::new(limit);
+    loop {
+        let state = match read_into(&server, &mut parser, &mut localbuf).await {
+            Ok(state) => state,
+            Err(e) => return Err(ServerError::ServerReadError(target, e.into())),
+        };
+        let (bytes, content) = parser.drain();
+        tee.write(bytes, content);
+        if state == ParseState::Done {
+            break;
+        }
+    }
+
+    let (bytes, digest) = tee.finish();
+    let mut response = RawHttpResponse::from(bytes);
+    response.digest = digest;
+    response.latency = Some(started.elapsed());
+
+    return Ok(response);
+}
+
+// Why main's response could not be streamed, and whether the client already
+// got part of it.
+struct Streamed {
+    error: ServerError,
+    answered: bool,
+    // The rest of the request body was malformed, over the limits or too
+    // slow.
+    rejected: Option<HttpError>,
+}
+
+// Sends the request to main and main's response to the client while they are
+// read, so a large body is never held whole. Returns the copies kept for the
+// shadows and the comparison, see Tee.
+async fn stream_main(
+    address: &str,
+    request: RawHttpRequest,
+    body: &mut Body,
+    client: &mut TcpStream,
+    proxy: &Proxy,
+) -> Result<(RawHttpRequest, RawHttpResponse), Streamed> {
+    let limit = proxy.config.tee_limit;
+    let address = String::from(address);
+    let started = Instant::now();
+    let failed = |error: ServerError, answered: bool| Streamed { error, answered, rejected: None };
+
+    let mut server = match TcpStream::connect(address.as_str()).await {
+        Ok(server) => server,
+        Err(e) => return Err(failed(ServerError::Unresponsive(address, Box::new(e)), false)),
+    };
+
+    // The rest of the request body is passed on as it arrives, the client has
+    // body_ms to send all of it.
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(proxy.config.client_timeouts.body_ms);
+    let mut localbuf = [0u8; BUFSIZE];
+    let mut tee = Tee::new(limit);
+    let mut pending = request.bytes.clone();
+    tee.write(request.bytes.clone(), std::mem::take(&mut body.content));
+    loop {
+        if let Err(e) = server.write_all(&pending).await {
+            return Err(failed(ServerError::ServerWriteError(address, Box::new(e)), false));
+        }
+        if body.parser.state() == ParseState::Done {
+            break;
+        }
+        let read = tokio::time::timeout_at(deadline, read_into(client, &mut body.parser, &mut localbuf)).await;
+        match read.unwrap_or(Err(ReadError::Http(HttpError::Timeout))) {
+            Ok(_) => {}
+            Err(ReadError::Http(e)) => {
+                let mut streamed = failed(ServerError::ServerReadError(String::from("client"), Box::new(e)), false);
+                streamed.rejected = Some(e);
+                return Err(streamed);
+            }
+            Err(e) => return Err(failed(ServerError::ServerReadError(String::from("client"), e.into()), false)),
+        }
+        let (bytes, content) = body.parser.drain();
+        tee.write(bytes.clone(), content);
+        pending = bytes;
+    }
+    let (bytes, digest) = tee.finish();
+    let mut kept = RawHttpRequest::from(bytes);
+    kept.digest = digest;
+
+    let mut parser = MessageParser::response(&method(&request));
+    let mut tee = Tee::new(limit);
+    let mut answered = false;
+    loop {
+        let state = match read_into(&server, &mut parser, &mut localbuf).await {
+            Ok(state) => state,
+            Err(e) => return Err(failed(ServerError::ServerReadError(address, e.into()), answered)),
+        };
+        let (bytes, content) = parser.drain();
+        if !bytes.is_empty() {
+            if let Err(e) = write_client(client, &bytes, proxy.write_timeout()).await {
+                return Err(failed(ServerError::ServerWriteError(String::from("client"), Box::new(e)), true));
+            }
+            answered = true;
+        }
+        tee.write(bytes, content);
+        if state == ParseState::Done {
+            break;
+        }
+    }
+
+    let (bytes, digest) = tee.finish();
+    let mut response = RawHttpResponse::from(bytes);
+    response.digest = digest;
+    response.latency = Some(started.elapsed());
+
+    return Ok((kept, response));
+}
+
+// Writes the parts with as few writes as the socket allows, without joining
+// them first.
+async fn write_parts(stream: &mut TcpStream, parts: &[Bytes]) -> Result<(), std::io::Error> {
+    let mut parts: Vec<Bytes> = parts.iter().filter(|p| !p.is_empty()).cloned().collect();
+    while !parts.is_empty() {
+        let slices: Vec<IoSlice> = parts.iter().map(|p| IoSlice::new(p)).collect();
+        let mut written = stream.write_vectored(&slices).await?;
+        if written == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::WriteZero));
+        }
+        while written > 0 {
+            let n = std::cmp::min(written, parts[0].len());
+            parts[0].advance(n);
+            written -= n;
+            if parts[0].is_empty() {
+                parts.remove(0);
+            }
+        }
+    }
+    return Ok(());
+}
+
+// The method decides whether the response can have a body.
+fn method(request: &RawHttpRequest) -> HttpMethod {
+    match request.request_line() {
+        Ok((method, _, _)) => method.clone(),
+        Err(_) => HttpMethod::Get,
+    }
+}
+
+// Why a message could not be read.
+enum ReadError {
+    Io(std::io::Error),
+    Http(HttpError),
+}
+
+impl From<ReadError> for Box<dyn std::error::Error + Send + Sync> {
+    fn from(value: ReadError) -> Self {
+        match value {
+            ReadError::Io(e) => Box::new(e),
+            ReadError::Http(e) => Box::new(e),
+        }
+    }
+}
+
+// Reads what the stream has once it is readable and feeds it to the parser.
+async fn read_into(stream: &TcpStream, parser: &mut MessageParser, buf: &mut [u8]) -> Result<ParseState, ReadError> {
+    loop {
+        stream.readable().await.map_err(ReadError::Io)?;
+
+        match stream.try_read(buf) {
+            Ok(0) => return parser.close().map_err(ReadError::Http),
+            Ok(n) => return parser.push(&buf[0..n]).map_err(ReadError::Http),
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                continue;
+            }
+            Err(e) => return Err(ReadError::Io(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::breaker::BreakerState;
+    use crate::config::{BreakerConfig, CanaryConfig, ClientTimeoutConfig};
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn trickling_body_times_out() {
+        // Main takes whatever it gets.
+        let main = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config {
+            main: main.local_addr().unwrap().to_string(),
+            client_timeouts: ClientTimeoutConfig {
+                body_ms: 300,
+                ..ClientTimeoutConfig::default()
+            },
+            ..Config::default()
+        };
+        let proxy = Proxy::new(config).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = main.accept().await.unwrap();
+            let _ = stream.read_to_end(&mut Vec::new()).await;
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+
+        // A byte of the body every 50ms never hits a timeout between reads.
+        let started = Instant::now();
+        let trickle = async {
+            client.write_all(b"POST / HTTP/1.1\r\nContent-Length: 100\r\n\r\n").await.unwrap();
+            let mut response = [0u8; 64];
+            loop {
+                tokio::select! {
+                    n = client.read(&mut response) => {
+                        let response = String::from_utf8_lossy(&response[..n.unwrap()]).into_owned();
+                        return (response, started.elapsed());
+                    }
+                    _ = tokio::time::sleep(Duration::from_millis(50)) => {
+                        let _ = client.write_all(b"a").await;
+                    }
+                }
+            }
+        };
+        let (handled, (response, elapsed)) = tokio::join!(handle_connection(accepted, &proxy), trickle);
+
+        assert!(matches!(handled, Ok(None)));
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout"), "{}", response);
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn sampling_by_template() {
+        let config = Config {
+            shadows: vec![ShadowConfig {
+                sampling: 0.5,
+                template_sampling: [(String::from("/users/{id}"), 0.0)].into_iter().collect(),
+                ..ShadowConfig::default()
+            }],
+            ..Config::default()
+        };
+        let proxy = Proxy::new(config).unwrap();
+        assert_eq!(proxy.template_sampling(0, "/users/{id}"), 0.0);
+        assert_eq!(proxy.template_sampling(0, "/orders/{id}"), 0.5);
+    }
+
+    // Answers every connection with an empty 200.
+    async fn upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await;
+            }
+        });
+        return address;
+    }
+
+    #[tokio::test]
+    async fn canary_probe_closes_breaker() {
+        // Capture mode, the exchange is never compared.
+        let config = Config {
+            main: upstream().await,
+            mode: Mode::Capture,
+            shadows: vec![ShadowConfig {
+                address: upstream().await,
+                breaker: BreakerConfig { failures: 1, cooldown_ms: 0 },
+                ..ShadowConfig::default()
+            }],
+            canary: Some(CanaryConfig {
+                shadow: String::from("shadow"),
+                fraction: 1.0,
+                ..CanaryConfig::default()
+            }),
+            ..Config::default()
+        };
+        let proxy = Proxy::new(config).unwrap();
+        proxy.breakers[0].record(false);
+        assert_eq!(proxy.breakers[0].state(), BreakerState::Open);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (accepted, _) = listener.accept().await.unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+
+        let handled = handle_connection(accepted, &proxy).await;
+        assert!(matches!(handled, Ok(Some(Exchange { canary: Some(_), .. }))));
+        assert_eq!(proxy.breakers[0].state(), BreakerState::Closed);
+    }
+}
@@ -0,0 +1,251 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::capture::{CaptureReader, CaptureRecord};
+use crate::compare::{self, ComparisonRecord, Outcome};
+use crate::config::Config;
+use crate::har;
+use crate::proxy::{request_server, request_shadow};
+use crate::rewrite;
+use crate::routing::Router;
+use crate::store::ResultStore;
+use crate::util::log;
+
+#[derive(Debug, Default)]
+struct Summary {
+    matched: AtomicUsize,
+    mismatched: AtomicUsize,
+    errors: AtomicUsize,
+}
+
+impl Summary {
+    fn count(&self, outcome: Outcome) {
+        let counter = match outcome {
+            Outcome::Match => &self.matched,
+            Outcome::Mismatch => &self.mismatched,
+            Outcome::Error => &self.errors,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Where the main response for a replayed request comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MainSource {
+    // Send the request to main again.
+    Live,
+    // Use the main response recorded in the capture, only the shadow is
+    // called. This is the deferred comparison for captures made in capture
+    // mode.
+    Recorded,
+}
+
+// Replays every request in the capture (or HAR) file against main and every
+// shadow, compares the responses and writes the results to the configured
+// store.
+pub fn run<T>(config: Config, capture: T, source: MainSource) -> Result<(), std::io::Error>
+where
+    T: AsRef<Path>,
+{
+    let is_har = capture
+        .as_ref()
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("har"));
+
+    let reader: Box<dyn Iterator<Item = Result<CaptureRecord, std::io::Error>>> = if is_har {
+        Box::new(har::import(capture)?.into_iter().map(Ok))
+    } else {
+        Box::new(CaptureReader::open(capture)?)
+    };
+    let store = ResultStore::open(&config.store, config.samples_per_cluster)?;
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+
+    let router = Router::new(&config)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let router = Arc::new(router);
+    let rewriters = rewrite::rewriters(&config)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let rewriters = Arc::new(rewriters);
+    let config = Arc::new(config);
+    let summaries: Arc<Vec<Summary>> = Arc::new(config.shadows.iter().map(|_| Summary::default()).collect());
+
+    let result = rt.block_on(async {
+        let (store_tx, store_rx) = tokio::sync::mpsc::channel::<ComparisonRecord>(1_000);
+        let store_task = tokio::spawn(store.run(store_rx));
+
+        let permits = Arc::new(Semaphore::new(std::cmp::max(config.replay.concurrency, 1)));
+
+        let mut ticker = config.replay.rate.filter(|r| *r > 0.0).map(|rate| {
+            let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / rate));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        });
+
+        let started = Instant::now();
+        let mut first: Option<DateTime<Utc>> = None;
+        let mut skipped = 0;
+        let mut failed = None;
+
+        for record in reader {
+            // A corrupt record is skipped, the reader picks up again at the
+            // next record header. Any other error stops reading, but the
+            // requests already in flight are still compared and stored.
+            let record = match record {
+                Ok(record) => record,
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData || e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                    warn!("skipping capture record: {}", e);
+                    skipped += 1;
+                    continue;
+                }
+                Err(e) => {
+                    failed = Some(e);
+                    break;
+                }
+            };
+
+            if let Some(scale) = config.replay.time_scale {
+                let first = *first.get_or_insert(record.timestamp);
+                let offset = (record.timestamp - first).to_std().unwrap_or_default();
+                tokio::time::sleep_until(started + offset.mul_f64(scale.max(0.0))).await;
+            }
+
+            if let Some(ticker) = ticker.as_mut() {
+                ticker.tick().await;
+            }
+
+            let permit = permits
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed");
+
+            let config = config.clone();
+            let router = router.clone();
+            let rewriters = rewriters.clone();
+            let summaries = summaries.clone();
+            let store_tx = store_tx.clone();
+
+            let mut request = record.request;
+            let span = info_span!("exchange", id = %request.ensure_request_id());
+
+            tokio::spawn(async move {
+                let target = match router.resolve(&request) {
+                    Some(target) => target,
+                    None => {
+                        info!("request matches no route, skipped");
+                        return;
+                    }
+                };
+
+                if source == MainSource::Recorded && record.response.is_none() {
+                    warn!("capture record has no main response");
+                    target.shadows.iter().for_each(|&i| summaries[i].count(Outcome::Error));
+                    return;
+                }
+
+                let mut shadow_requests = JoinSet::new();
+                for &index in target.shadows.iter() {
+                    let config = config.clone();
+                    let rewriters = rewriters.clone();
+                    let request = request.clone();
+                    shadow_requests.spawn(async move {
+                        let response = request_shadow(&config.shadows[index], &rewriters[index], &request, config.tee_limit).await;
+                        (index, response)
+                    });
+                }
+
+                let main_response = match record.response {
+                    Some(recorded) if source == MainSource::Recorded => Ok(recorded),
+                    _ => request_server(target.main.as_str(), &request, config.tee_limit).await,
+                };
+
+                let mut shadow_responses: Vec<_> = config.shadows.iter().map(|_| None).collect();
+                while let Some(joined) = shadow_requests.join_next().await {
+                    if let Ok((index, response)) = joined {
+                        shadow_responses[index] = Some(response);
+                    }
+                }
+
+                drop(permit);
+
+                let main_response = match main_response {
+                    Ok(r) => r,
+                    Err(e) => {
+                        warn!("error with main: {}", e);
+                        target.shadows.iter().for_each(|&i| summaries[i].count(Outcome::Error));
+                        return;
+                    }
+                };
+
+                for (index, shadow_response) in shadow_responses.into_iter().enumerate() {
+                    let shadow_response = match shadow_response {
+                        Some(r) => r,
+                        None => continue, /* the shadow request panicked */
+                    };
+
+                    let shadow = &config.shadows[index];
+                    let result = compare::evaluate(
+                        shadow.name.as_str(),
+                        &target.route,
+                        router.templates(),
+                        request.clone(),
+                        main_response.clone(),
+                        shadow_response,
+                        shadow.rules(&config),
+                    );
+
+                    summaries[index].count(result.outcome);
+
+                    if let Some(e) = &result.error {
+                        warn!(target: log::SHADOW, shadow = %shadow.name, "{}", e);
+                    }
+
+                    let _ = store_tx.send(result).await;
+                }
+            }.instrument(span));
+        }
+
+        // Every replay task holds a sender, so the store task only finishes
+        // once all requests in flight have been compared and stored.
+        drop(store_tx);
+        let _ = store_task.await;
+
+        if let Some(e) = failed {
+            return Err(e);
+        }
+        Ok::<usize, std::io::Error>(skipped)
+    });
+
+    for (shadow, summary) in config.shadows.iter().zip(summaries.iter()) {
+        info!(
+            target: log::SHADOW,
+            "replay finished for {}: {} matched, {} mismatched, {} errors",
+            shadow.name,
+            summary.matched.load(Ordering::Relaxed),
+            summary.mismatched.load(Ordering::Relaxed),
+            summary.errors.load(Ordering::Relaxed)
+        );
+    }
+
+    match result {
+        Ok(skipped) if skipped > 0 => warn!("replay skipped {} corrupt capture records", skipped),
+        Ok(_) => {}
+        Err(e) => {
+            error!("replay stopped reading the capture: {}", e);
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
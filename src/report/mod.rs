@@ -0,0 +1,645 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::compare::{ComparisonRecord, Difference, Outcome};
+use crate::config::Config;
+use crate::store;
+
+// Number of categories in the top list, each gets one sample diff.
+const TOP_CATEGORIES: usize = 10;
+// Number of mismatch clusters listed, and request IDs shown per cluster.
+const TOP_CLUSTERS: usize = 20;
+const CLUSTER_SAMPLES: usize = 3;
+// Responses shown in the HTML samples are cut off at this many bytes.
+const MAX_SAMPLE_BODY: usize = 4 * 1024;
+
+// Which records go into the report and where it is written. Without an HTML
+// or Markdown file the Markdown summary is written to stdout.
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub route: Option<String>,
+    pub shadow: Option<String>,
+    pub html: Option<PathBuf>,
+    pub markdown: Option<PathBuf>,
+}
+
+impl Options {
+    fn includes(&self, record: &ComparisonRecord) -> bool {
+        if self.since.is_some_and(|since| record.timestamp < since) {
+            return false;
+        }
+        if self.until.is_some_and(|until| record.timestamp >= until) {
+            return false;
+        }
+        if self.route.as_ref().is_some_and(|route| route != &record.route) {
+            return false;
+        }
+        if self.shadow.as_ref().is_some_and(|shadow| shadow != &record.shadow) {
+            return false;
+        }
+        return true;
+    }
+}
+
+// Reads a point in time for --since and --until, either RFC 3339 or a
+// duration before now like 30m, 12h or 7d.
+pub fn parse_time(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+
+    let invalid = || format!("invalid time {}, expected RFC 3339 or a duration like 30m", value);
+    let (amount, unit) = value.split_at(value.len().saturating_sub(1));
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let ago = match unit {
+        "s" => Duration::seconds(amount),
+        "m" => Duration::minutes(amount),
+        "h" => Duration::hours(amount),
+        "d" => Duration::days(amount),
+        _ => return Err(invalid()),
+    };
+    return Ok(now - ago);
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    matched: usize,
+    mismatched: usize,
+    errors: usize,
+}
+
+impl Counts {
+    fn add(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Match => self.matched += 1,
+            Outcome::Mismatch => self.mismatched += 1,
+            Outcome::Error => self.errors += 1,
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.matched + self.mismatched + self.errors
+    }
+
+    fn match_rate(&self) -> f64 {
+        if self.total() == 0 {
+            return 0.0;
+        }
+        return self.matched as f64 / self.total() as f64;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Endpoint {
+    counts: Counts,
+    main_latency: Vec<f64>,
+    shadow_latency: Vec<f64>,
+    // Shadow minus main latency, for exchanges where both are known.
+    deltas: Vec<f64>,
+}
+
+// Mismatches with the same signature.
+struct Cluster<'a> {
+    signature: String,
+    description: String,
+    count: usize,
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+    // Request IDs of the mismatches stored with their exchange.
+    samples: Vec<&'a str>,
+}
+
+struct Report<'a> {
+    records: usize,
+    first: Option<DateTime<Utc>>,
+    last: Option<DateTime<Utc>>,
+    shadows: BTreeMap<&'a str, Counts>,
+    // By (shadow, method path).
+    endpoints: BTreeMap<(&'a str, String), Endpoint>,
+    // Categories by the number of mismatches they occur in, with the first
+    // mismatch as the sample.
+    categories: Vec<(String, usize, &'a ComparisonRecord)>,
+    // By count, largest first.
+    clusters: Vec<Cluster<'a>>,
+    mismatches: usize,
+}
+
+pub fn run(config: Config, options: Options) -> Result<(), std::io::Error> {
+    let records = store::read_records(&config.store)?;
+    let records: Vec<ComparisonRecord> = records.into_iter().filter(|r| options.includes(r)).collect();
+    let report = Report::new(&records);
+
+    if let Some(path) = &options.html {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(report.html(&options).as_bytes())?;
+        file.flush()?;
+    }
+    if let Some(path) = &options.markdown {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(report.markdown(&options).as_bytes())?;
+        file.flush()?;
+    }
+    if options.html.is_none() && options.markdown.is_none() {
+        std::io::stdout().write_all(report.markdown(&options).as_bytes())?;
+    }
+
+    return Ok(());
+}
+
+impl<'a> Report<'a> {
+    fn new(records: &'a [ComparisonRecord]) -> Report<'a> {
+        let mut shadows: BTreeMap<&str, Counts> = BTreeMap::new();
+        let mut endpoints: BTreeMap<(&str, String), Endpoint> = BTreeMap::new();
+        let mut categories: BTreeMap<String, (usize, &ComparisonRecord)> = BTreeMap::new();
+        let mut clusters: BTreeMap<String, Cluster> = BTreeMap::new();
+        let mut mismatches = 0;
+
+        for record in records.iter() {
+            shadows.entry(record.shadow.as_str()).or_default().add(record.outcome);
+
+            let endpoint = endpoints.entry((record.shadow.as_str(), endpoint(record))).or_default();
+            endpoint.counts.add(record.outcome);
+            if let Some(main) = record.main_latency_ms {
+                endpoint.main_latency.push(main);
+            }
+            if let Some(shadow) = record.shadow_latency_ms {
+                endpoint.shadow_latency.push(shadow);
+            }
+            if let (Some(main), Some(shadow)) = (record.main_latency_ms, record.shadow_latency_ms) {
+                endpoint.deltas.push(shadow - main);
+            }
+
+            if record.outcome != Outcome::Mismatch {
+                continue;
+            }
+            mismatches += 1;
+
+            // Records stored before signatures were added get one here.
+            let signature = record.signature.clone().unwrap_or_else(|| record.signature());
+            let cluster = clusters.entry(signature.clone()).or_insert_with(|| Cluster {
+                signature,
+                description: record.cluster(),
+                count: 0,
+                first: record.timestamp,
+                last: record.timestamp,
+                samples: Vec::new(),
+            });
+            cluster.count += 1;
+            cluster.first = cluster.first.min(record.timestamp);
+            cluster.last = cluster.last.max(record.timestamp);
+            if record.exchange.is_some() && cluster.samples.len() < CLUSTER_SAMPLES {
+                cluster.samples.push(record.request_id.as_str());
+            }
+
+            // A record counts once per category, even when e.g. a header
+            // differs twice.
+            let mut seen: Vec<String> = record.differences.iter().map(category).collect();
+            seen.sort();
+            seen.dedup();
+            for c in seen.into_iter() {
+                categories.entry(c).or_insert((0, record)).0 += 1;
+            }
+        }
+
+        let mut categories: Vec<(String, usize, &ComparisonRecord)> = categories
+            .into_iter()
+            .map(|(c, (count, sample))| (c, count, sample))
+            .collect();
+        categories.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let mut clusters: Vec<Cluster> = clusters.into_values().collect();
+        clusters.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.description.cmp(&b.description)));
+
+        Report {
+            records: records.len(),
+            first: records.iter().map(|r| r.timestamp).min(),
+            last: records.iter().map(|r| r.timestamp).max(),
+            shadows,
+            endpoints,
+            categories,
+            clusters,
+            mismatches,
+        }
+    }
+
+    fn window(&self) -> String {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => format!("{} to {}", first.to_rfc3339(), last.to_rfc3339()),
+            _ => String::from("no records"),
+        }
+    }
+
+    fn markdown(&self, options: &Options) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# Comparison report\n");
+        let _ = writeln!(out, "{} records, {}.", self.records, self.window());
+        let filters = filters(options);
+        if !filters.is_empty() {
+            let _ = writeln!(out, "Filtered on {}.", filters);
+        }
+
+        let _ = writeln!(out, "\n## Shadows\n");
+        let _ = writeln!(out, "| Shadow | Requests | Match rate | Matched | Mismatched | Errors |");
+        let _ = writeln!(out, "|---|---:|---:|---:|---:|---:|");
+        for (shadow, counts) in self.shadows.iter() {
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} | {} | {} |",
+                md(shadow),
+                counts.total(),
+                percent(counts.match_rate()),
+                counts.matched,
+                counts.mismatched,
+                counts.errors
+            );
+        }
+
+        let _ = writeln!(out, "\n## Endpoints\n");
+        let _ = writeln!(
+            out,
+            "| Shadow | Endpoint | Requests | Match rate | Mismatched | Errors | Main ms | Shadow ms | Delta ms | Delta p95 ms |"
+        );
+        let _ = writeln!(out, "|---|---|---:|---:|---:|---:|---:|---:|---:|---:|");
+        for ((shadow, name), endpoint) in self.endpoints.iter() {
+            let _ = writeln!(
+                out,
+                "| {} | `{}` | {} | {} | {} | {} | {} | {} | {} | {} |",
+                md(shadow),
+                name.replace('`', "'"),
+                endpoint.counts.total(),
+                percent(endpoint.counts.match_rate()),
+                endpoint.counts.mismatched,
+                endpoint.counts.errors,
+                millis(mean(&endpoint.main_latency)),
+                millis(mean(&endpoint.shadow_latency)),
+                millis(mean(&endpoint.deltas)),
+                millis(p95(&endpoint.deltas))
+            );
+        }
+
+        let _ = writeln!(out, "\n## Top mismatch categories\n");
+        let _ = writeln!(out, "| Category | Mismatches | Share |");
+        let _ = writeln!(out, "|---|---:|---:|");
+        for (c, count, _) in self.categories.iter().take(TOP_CATEGORIES) {
+            let share = *count as f64 / self.mismatches as f64;
+            let _ = writeln!(out, "| {} | {} | {} |", md(c), count, percent(share));
+        }
+
+        let _ = writeln!(out, "\n## Mismatch clusters\n");
+        let _ = writeln!(out, "| Signature | Failure | Mismatches | First seen | Last seen | Samples |");
+        let _ = writeln!(out, "|---|---|---:|---|---|---|");
+        for cluster in self.clusters.iter().take(TOP_CLUSTERS) {
+            let _ = writeln!(
+                out,
+                "| `{}` | {} | {} | {} | {} | {} |",
+                cluster.signature,
+                md(&cluster.description),
+                cluster.count,
+                cluster.first.to_rfc3339(),
+                cluster.last.to_rfc3339(),
+                md(&cluster.samples.join(", "))
+            );
+        }
+
+        let _ = writeln!(out, "\n## Sample diffs");
+        for (c, _, sample) in self.categories.iter().take(TOP_CATEGORIES) {
+            let _ = writeln!(out, "\n### {}\n", md(c));
+            let _ = writeln!(
+                out,
+                "`{} {}` to {}, request {}\n",
+                sample.method,
+                sample.target.replace('`', "'"),
+                md(&sample.shadow),
+                md(&sample.request_id)
+            );
+            let _ = writeln!(out, "| Difference | Main | Shadow |");
+            let _ = writeln!(out, "|---|---|---|");
+            for d in sample.differences.iter() {
+                let (main, shadow) = values(d);
+                let _ = writeln!(out, "| {} | {} | {} |", md(&category(d)), md(&main), md(&shadow));
+            }
+        }
+
+        return out;
+    }
+
+    fn html(&self, options: &Options) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">");
+        let _ = writeln!(out, "<title>Comparison report</title>\n<style>{}</style>\n</head>\n<body>", STYLE);
+        let _ = writeln!(out, "<h1>Comparison report</h1>");
+        let _ = writeln!(out, "<p>{} records, {}.</p>", self.records, html(&self.window()));
+        let filters = filters(options);
+        if !filters.is_empty() {
+            let _ = writeln!(out, "<p>Filtered on {}.</p>", html(&filters));
+        }
+
+        let _ = writeln!(out, "<h2>Shadows</h2>\n<table>");
+        let _ = writeln!(
+            out,
+            "<tr><th>Shadow</th><th>Requests</th><th>Match rate</th><th>Matched</th><th>Mismatched</th><th>Errors</th></tr>"
+        );
+        for (shadow, counts) in self.shadows.iter() {
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                html(shadow),
+                counts.total(),
+                percent(counts.match_rate()),
+                counts.matched,
+                counts.mismatched,
+                counts.errors
+            );
+        }
+        let _ = writeln!(out, "</table>");
+
+        let _ = writeln!(out, "<h2>Endpoints</h2>\n<table>");
+        let _ = writeln!(
+            out,
+            "<tr><th>Shadow</th><th>Endpoint</th><th>Requests</th><th>Match rate</th><th>Mismatched</th>\
+             <th>Errors</th><th>Main ms</th><th>Shadow ms</th><th>Delta ms</th><th>Delta p95 ms</th></tr>"
+        );
+        for ((shadow, name), endpoint) in self.endpoints.iter() {
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+                 <td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                html(shadow),
+                html(name),
+                endpoint.counts.total(),
+                percent(endpoint.counts.match_rate()),
+                endpoint.counts.mismatched,
+                endpoint.counts.errors,
+                millis(mean(&endpoint.main_latency)),
+                millis(mean(&endpoint.shadow_latency)),
+                millis(mean(&endpoint.deltas)),
+                millis(p95(&endpoint.deltas))
+            );
+        }
+        let _ = writeln!(out, "</table>");
+
+        let _ = writeln!(out, "<h2>Top mismatch categories</h2>\n<table>");
+        let _ = writeln!(out, "<tr><th>Category</th><th>Mismatches</th><th>Share</th></tr>");
+        for (c, count, _) in self.categories.iter().take(TOP_CATEGORIES) {
+            let share = *count as f64 / self.mismatches as f64;
+            let _ = writeln!(out, "<tr><td>{}</td><td>{}</td><td>{}</td></tr>", html(c), count, percent(share));
+        }
+        let _ = writeln!(out, "</table>");
+
+        let _ = writeln!(out, "<h2>Mismatch clusters</h2>\n<table>");
+        let _ = writeln!(
+            out,
+            "<tr><th>Signature</th><th>Failure</th><th>Mismatches</th><th>First seen</th><th>Last seen</th><th>Samples</th></tr>"
+        );
+        for cluster in self.clusters.iter().take(TOP_CLUSTERS) {
+            let _ = writeln!(
+                out,
+                "<tr><td><code>{}</code></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                cluster.signature,
+                html(&cluster.description),
+                cluster.count,
+                cluster.first.to_rfc3339(),
+                cluster.last.to_rfc3339(),
+                html(&cluster.samples.join(", "))
+            );
+        }
+        let _ = writeln!(out, "</table>");
+
+        let _ = writeln!(out, "<h2>Sample diffs</h2>");
+        for (c, _, sample) in self.categories.iter().take(TOP_CATEGORIES) {
+            let _ = writeln!(out, "<h3>{}</h3>", html(c));
+            let _ = writeln!(
+                out,
+                "<p><code>{} {}</code> to {}, request {}</p>",
+                html(&sample.method),
+                html(&sample.target),
+                html(&sample.shadow),
+                html(&sample.request_id)
+            );
+            let _ = writeln!(out, "<table>\n<tr><th>Difference</th><th>Main</th><th>Shadow</th></tr>");
+            for d in sample.differences.iter() {
+                let (main, shadow) = values(d);
+                let _ = writeln!(
+                    out,
+                    "<tr><td>{}</td><td><pre>{}</pre></td><td><pre>{}</pre></td></tr>",
+                    html(&category(d)),
+                    html(&main),
+                    html(&shadow)
+                );
+            }
+            let _ = writeln!(out, "</table>");
+
+            if let Some(exchange) = &sample.exchange {
+                let _ = writeln!(
+                    out,
+                    "<div class=\"side\"><pre>{}</pre><pre>{}</pre></div>",
+                    html(&sample_body(&exchange.main)),
+                    html(&sample_body(&exchange.shadow))
+                );
+            }
+        }
+
+        let _ = writeln!(out, "</body>\n</html>");
+        return out;
+    }
+}
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}\
+table{border-collapse:collapse;margin-bottom:1em}\
+td,th{border:1px solid #ccc;padding:4px 8px;text-align:left;vertical-align:top}\
+pre{margin:0;white-space:pre-wrap;word-break:break-all}\
+.side{display:grid;grid-template-columns:1fr 1fr;gap:1em}\
+.side pre{border:1px solid #ccc;padding:8px;background:#f8f8f8}";
+
+// The method and route template of the request.
+fn endpoint(record: &ComparisonRecord) -> String {
+    return format!("{} {}", record.method, record.template());
+}
+
+fn category(difference: &Difference) -> String {
+    match difference {
+        Difference::Status { main, shadow } => format!("status {} -> {}", code(*main), code(*shadow)),
+        Difference::Header { name, .. } => format!("header {}", name),
+        Difference::Body { path, .. } => format!("body {}", path),
+        Difference::Bytes { offset: Some(o), .. } => format!("body bytes from offset {}", o),
+        Difference::Bytes { offset: None, .. } => String::from("body bytes"),
+    }
+}
+
+fn values(difference: &Difference) -> (String, String) {
+    let value = |v: &Option<String>| v.clone().unwrap_or_else(|| String::from("(missing)"));
+    match difference {
+        Difference::Status { main, shadow } => (code(*main), code(*shadow)),
+        Difference::Header { main, shadow, .. } | Difference::Body { main, shadow, .. } => {
+            (value(main), value(shadow))
+        }
+        Difference::Bytes { main, shadow, main_context, shadow_context, .. } => (
+            format!("{}\n{}", main, main_context),
+            format!("{}\n{}", shadow, shadow_context),
+        ),
+    }
+}
+
+fn code(code: Option<u16>) -> String {
+    code.map(|c| c.to_string()).unwrap_or_else(|| String::from("?"))
+}
+
+fn filters(options: &Options) -> String {
+    let mut filters: Vec<String> = Vec::new();
+    if let Some(since) = options.since {
+        filters.push(format!("since {}", since.to_rfc3339()));
+    }
+    if let Some(until) = options.until {
+        filters.push(format!("until {}", until.to_rfc3339()));
+    }
+    if let Some(route) = &options.route {
+        filters.push(format!("route {}", route));
+    }
+    if let Some(shadow) = &options.shadow {
+        filters.push(format!("shadow {}", shadow));
+    }
+    return filters.join(", ");
+}
+
+fn sample_body(bytes: &[u8]) -> String {
+    let mut text = String::from_utf8_lossy(&bytes[..bytes.len().min(MAX_SAMPLE_BODY)]).into_owned();
+    if bytes.len() > MAX_SAMPLE_BODY {
+        text.push_str("\n...");
+    }
+    return text;
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    return Some(values.iter().sum::<f64>() / values.len() as f64);
+}
+
+fn p95(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let rank = (sorted.len() as f64 * 0.95).ceil() as usize;
+    return Some(sorted[rank.saturating_sub(1)]);
+}
+
+fn millis(value: Option<f64>) -> String {
+    value.map(|v| format!("{:.1}", v)).unwrap_or_else(|| String::from("-"))
+}
+
+fn percent(fraction: f64) -> String {
+    format!("{:.1}%", fraction * 100.0)
+}
+
+fn html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Table cells can not contain pipes or line breaks.
+fn md(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(shadow: &str, target: &str, outcome: Outcome, differences: Vec<Difference>) -> ComparisonRecord {
+        ComparisonRecord {
+            timestamp: DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap().with_timezone(&Utc),
+            request_id: String::from("1"),
+            shadow: String::from(shadow),
+            route: String::from("users"),
+            method: String::from("GET"),
+            target: String::from(target),
+            template: String::new(),
+            outcome,
+            main_status: Some(200),
+            shadow_status: Some(200),
+            main_latency_ms: Some(10.0),
+            shadow_latency_ms: Some(14.0),
+            differences,
+            signature: None,
+            error: None,
+            canary: false,
+            exchange: None,
+        }
+    }
+
+    #[test]
+    fn times() {
+        let now = DateTime::parse_from_rfc3339("2024-05-01T10:00:00Z").unwrap().with_timezone(&Utc);
+        assert_eq!(parse_time("90m", now).unwrap().to_rfc3339(), "2024-05-01T08:30:00+00:00");
+        assert_eq!(parse_time("2d", now).unwrap().to_rfc3339(), "2024-04-29T10:00:00+00:00");
+        assert_eq!(
+            parse_time("2024-04-01T00:00:00+02:00", now).unwrap().to_rfc3339(),
+            "2024-03-31T22:00:00+00:00"
+        );
+        assert!(parse_time("yesterday", now).is_err());
+        assert!(parse_time("", now).is_err());
+    }
+
+    #[test]
+    fn summary() {
+        let status = Difference::Status { main: Some(200), shadow: Some(500) };
+        let header = Difference::Header {
+            name: String::from("Content-Type"),
+            main: Some(String::from("a|b")),
+            shadow: None,
+        };
+        let records = vec![
+            record("a", "/users/1?x=1", Outcome::Match, vec![]),
+            record("a", "/users/1", Outcome::Mismatch, vec![status.clone(), header.clone(), header]),
+            record("a", "/users/1", Outcome::Mismatch, vec![status]),
+            record("b", "/users/1", Outcome::Error, vec![]),
+        ];
+
+        let report = Report::new(&records);
+        assert_eq!(report.mismatches, 2);
+        assert_eq!(report.categories[0].0, "status 200 -> 500");
+        assert_eq!(report.categories[0].1, 2);
+        assert_eq!(report.categories[1].1, 1);
+        assert_eq!(report.clusters.len(), 2);
+        assert_eq!(report.clusters[0].description, "GET /users/{id} 200->200");
+        assert_eq!(report.clusters[1].description, "GET /users/{id} 200->200 header:content-type");
+
+        let markdown = report.markdown(&Options::default());
+        assert!(markdown.contains("| a | `GET /users/{id}` | 3 | 33.3% | 2 | 0 | 10.0 | 14.0 | 4.0 | 4.0 |"));
+        assert!(markdown.contains("| header Content-Type | a\\|b | (missing) |"));
+
+        let html = report.html(&Options::default());
+        assert!(html.contains("<td>status 200 -&gt; 500</td><td>2</td><td>100.0%</td>"));
+    }
+
+    #[test]
+    fn filters() {
+        let options = Options {
+            shadow: Some(String::from("a")),
+            since: Some(DateTime::parse_from_rfc3339("2024-05-01T09:00:00Z").unwrap().with_timezone(&Utc)),
+            ..Options::default()
+        };
+        assert!(options.includes(&record("a", "/", Outcome::Match, vec![])));
+        assert!(!options.includes(&record("b", "/", Outcome::Match, vec![])));
+
+        let options = Options {
+            route: Some(String::from("orders")),
+            ..Options::default()
+        };
+        assert!(!options.includes(&record("a", "/", Outcome::Match, vec![])));
+    }
+}
@@ -0,0 +1,419 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use bytes::Bytes;
+use regex::bytes::Regex as BytesRegex;
+use regex::Regex;
+
+use crate::config::{Config, Replacement, RewriteConfig};
+use crate::http::request::RawHttpRequest;
+use crate::http::target;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    Shadow,
+    Header(String),
+}
+
+// A header value template, environment variables are already filled in.
+#[derive(Debug, Clone, PartialEq)]
+struct Template {
+    parts: Vec<Part>,
+}
+
+// The compiled rewrite rules of one shadow.
+#[derive(Debug)]
+pub struct Rewriter {
+    shadow: String,
+    set_headers: Vec<(String, Template)>,
+    remove_headers: Vec<String>,
+    path: Vec<(Regex, String)>,
+    remove_query: Vec<String>,
+    body: Vec<(BytesRegex, String)>,
+}
+
+// The copy of a request that is sent to a shadow. The body shares the buffer
+// of the original request, unless a body rule changed it.
+#[derive(Debug, Clone)]
+pub struct Rewritten {
+    pub head: Bytes,
+    pub body: Bytes,
+}
+
+// Compiles the rewrite rules of every shadow, in the order of Config::shadows.
+pub fn rewriters(config: &Config) -> Result<Vec<Rewriter>, String> {
+    config
+        .shadows
+        .iter()
+        .map(|s| Rewriter::new(&s.rewrite, &s.name))
+        .collect()
+}
+
+impl Rewriter {
+    pub fn new(rewrite: &RewriteConfig, shadow: &str) -> Result<Rewriter, String> {
+        let mut set_headers: Vec<(String, Template)> = Vec::new();
+        for (name, value) in rewrite.set_headers.iter() {
+            set_headers.push((name.clone(), Template::parse(value)?));
+        }
+        if let Some(marker) = &rewrite.marker {
+            set_headers.push((marker.header.clone(), Template::parse(&marker.value)?));
+        }
+
+        let path = rewrite
+            .path
+            .iter()
+            .map(|r: &Replacement| {
+                Regex::new(&r.pattern)
+                    .map(|re| (re, r.replace.clone()))
+                    .map_err(|e| format!("bad path pattern for shadow {}: {}", shadow, e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let body = rewrite
+            .body
+            .iter()
+            .map(|r: &Replacement| {
+                BytesRegex::new(&r.pattern)
+                    .map(|re| (re, r.replace.clone()))
+                    .map_err(|e| format!("bad body pattern for shadow {}: {}", shadow, e))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Rewriter {
+            shadow: String::from(shadow),
+            set_headers,
+            remove_headers: rewrite.remove_headers.clone(),
+            path,
+            remove_query: rewrite.remove_query.clone(),
+            body,
+        })
+    }
+
+    // Builds the copy of the request that is sent to the shadow. The head is
+    // rewritten on bytes: header lines no rule applies to, with their folds
+    // and line endings, are copied as they are. Only the request line (when
+    // the target changes) and the headers that are set are written anew.
+    pub fn apply(&self, request: &RawHttpRequest) -> Rewritten {
+        let bytes = &request.bytes;
+
+        let head_end = match find_head_end(bytes) {
+            Some(end) => end,
+            None => {
+                /* not a complete head, leave as is */
+                return Rewritten {
+                    head: bytes.clone(),
+                    body: Bytes::new(),
+                };
+            }
+        };
+
+        let lines = lines(&bytes[..head_end.0]);
+        let request_line = lines.first().cloned().unwrap_or(0..0);
+        let fields = fields(bytes, &lines[1.min(lines.len())..]);
+        let mut body = bytes.slice(head_end.1..);
+
+        // Headers written anew, with their values.
+        let mut setting: Vec<(String, String)> = Vec::new();
+
+        let chunked = request
+            .header("transfer-encoding")
+            .is_some_and(|v| v.to_ascii_lowercase().contains("chunked"));
+        // The body is only copied when a rule matches.
+        if !self.body.is_empty() && !chunked {
+            let mut rewritten: Option<Vec<u8>> = None;
+            for (re, replace) in self.body.iter() {
+                let current = rewritten.as_deref().unwrap_or(&body);
+                let replaced = match re.replace_all(current, replace.as_bytes()) {
+                    Cow::Owned(replaced) => Some(replaced),
+                    Cow::Borrowed(_) => None,
+                };
+                if let Some(replaced) = replaced {
+                    rewritten = Some(replaced);
+                }
+            }
+            if let Some(rewritten) = rewritten {
+                body = Bytes::from(rewritten);
+                set(&mut setting, "Content-Length", body.len().to_string());
+            }
+        }
+
+        // Templates see the headers of the original request.
+        for (name, template) in self.set_headers.iter() {
+            set(&mut setting, name, template.render(&self.shadow, request));
+        }
+
+        let mut out = Vec::with_capacity(head_end.1 + 128);
+        match self.target(&bytes[request_line.clone()]) {
+            Some(line) => out.extend_from_slice(line.as_bytes()),
+            None => out.extend_from_slice(&bytes[request_line]),
+        }
+
+        // A header that is set replaces the first line with its name, in
+        // place, and any others are dropped.
+        let mut written = vec![false; setting.len()];
+        for (name, range) in fields.into_iter() {
+            if let Some(name) = name {
+                if let Some(i) = setting.iter().position(|(n, _)| n.as_bytes().eq_ignore_ascii_case(name)) {
+                    if !written[i] {
+                        let line = format!("{}: {}\r\n", String::from_utf8_lossy(name), setting[i].1);
+                        out.extend_from_slice(line.as_bytes());
+                        written[i] = true;
+                    }
+                    continue;
+                }
+                if self.remove_headers.iter().any(|r| r.as_bytes().eq_ignore_ascii_case(name)) {
+                    continue;
+                }
+            }
+            out.extend_from_slice(&bytes[range]);
+        }
+        for ((name, value), _) in setting.iter().zip(written).filter(|(_, written)| !written) {
+            out.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        out.extend_from_slice(&bytes[head_end.0..head_end.1]);
+
+        Rewritten {
+            head: Bytes::from(out),
+            body,
+        }
+    }
+
+    // The request line with the target rewritten and its own line ending,
+    // None when no rule changes it.
+    fn target(&self, line: &[u8]) -> Option<String> {
+        if self.path.is_empty() && self.remove_query.is_empty() {
+            return None;
+        }
+        let line = std::str::from_utf8(line).ok()?;
+        let content = line.trim_end_matches(['\r', '\n']);
+        let ending = &line[content.len()..];
+
+        let mut parts = content.splitn(3, ' ');
+        let (method, original, version) = (parts.next()?, parts.next()?, parts.next()?);
+        let mut target = String::from(original);
+        for (re, replace) in self.path.iter() {
+            target = re.replace_all(&target, replace.as_str()).into_owned();
+        }
+        if !self.remove_query.is_empty() {
+            target = target::remove_params(&target, &self.remove_query);
+        }
+        if target == original {
+            return None;
+        }
+        return Some(format!("{} {} {}{}", method, target, version, ending));
+    }
+}
+
+impl Template {
+    fn parse(text: &str) -> Result<Template, String> {
+        let mut parts: Vec<Part> = Vec::new();
+        let mut rest = text;
+
+        while let Some(open) = rest.find('{') {
+            let close = rest[open..]
+                .find('}')
+                .map(|c| open + c)
+                .ok_or_else(|| format!("unclosed placeholder in {}", text))?;
+            if open > 0 {
+                parts.push(Part::Literal(String::from(&rest[..open])));
+            }
+
+            let placeholder = &rest[open + 1..close];
+            match placeholder.split_once(':') {
+                None if placeholder == "shadow" => parts.push(Part::Shadow),
+                Some(("header", name)) => parts.push(Part::Header(String::from(name))),
+                Some(("env", name)) => {
+                    let value = std::env::var(name).map_err(|_| format!("environment variable {} is not set", name))?;
+                    parts.push(Part::Literal(value));
+                }
+                _ => return Err(format!("unknown placeholder {{{}}} in {}", placeholder, text)),
+            }
+            rest = &rest[close + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Literal(String::from(rest)));
+        }
+
+        Ok(Template { parts })
+    }
+
+    fn render(&self, shadow: &str, request: &RawHttpRequest) -> String {
+        self.parts
+            .iter()
+            .map(|p| match p {
+                Part::Literal(text) => text.as_str(),
+                Part::Shadow => shadow,
+                Part::Header(name) => request.header(name).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+// Start and end of the empty line that ends the head: (end of the header
+// lines, start of the body).
+fn find_head_end(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut cursor = 0;
+    while let Some(lf) = bytes[cursor..].iter().position(|&b| b == b'\n') {
+        let line_end = cursor + lf;
+        let line = &bytes[cursor..line_end];
+        if line.is_empty() || line == b"\r" {
+            return Some((cursor, line_end + 1));
+        }
+        cursor = line_end + 1;
+    }
+    None
+}
+
+// Byte ranges of the lines of a head, each with its line ending.
+fn lines(head: &[u8]) -> Vec<Range<usize>> {
+    let mut lines = Vec::new();
+    let mut cursor = 0;
+    while cursor < head.len() {
+        let end = match head[cursor..].iter().position(|&b| b == b'\n') {
+            Some(lf) => cursor + lf + 1,
+            None => head.len(),
+        };
+        lines.push(cursor..end);
+        cursor = end;
+    }
+    return lines;
+}
+
+// The header fields of a head: the name, None for a line without a colon,
+// and the byte range of the field line with its obs-fold continuations.
+fn fields<'a>(bytes: &'a [u8], lines: &[Range<usize>]) -> Vec<(Option<&'a [u8]>, Range<usize>)> {
+    let mut fields: Vec<(Option<&[u8]>, Range<usize>)> = Vec::new();
+    for line in lines.iter() {
+        let folded = matches!(bytes[line.start], b' ' | b'\t');
+        match fields.last_mut() {
+            Some((_, range)) if folded => range.end = line.end,
+            _ => {
+                let content = &bytes[line.clone()];
+                let name = content.iter().position(|&b| b == b':').map(|colon| content[..colon].trim_ascii());
+                fields.push((name, line.clone()));
+            }
+        }
+    }
+    return fields;
+}
+
+// Sets the value of a header, replacing an earlier value for the same name.
+fn set(headers: &mut Vec<(String, String)>, name: &str, value: String) {
+    match headers.iter_mut().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        Some(header) => header.1 = value,
+        None => headers.push((String::from(name), value)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Marker;
+
+    fn rewrite(config: RewriteConfig, request: &str) -> String {
+        let rewriter = Rewriter::new(&config, "refactor").expect("valid rules");
+        let rewritten = rewriter.apply(&RawHttpRequest::from(Vec::from(request)));
+        String::from_utf8([rewritten.head, rewritten.body].concat()).unwrap()
+    }
+
+    #[test]
+    fn marker_is_added_by_default() {
+        let actual = rewrite(RewriteConfig::default(), "GET /api HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(actual, "GET /api HTTP/1.1\r\nHost: a\r\nX-Shadow-Request: refactor\r\n\r\n");
+    }
+
+    #[test]
+    fn headers_and_path() {
+        let config = RewriteConfig {
+            set_headers: [
+                (String::from("host"), String::from("shadow.internal")),
+                (String::from("X-Forwarded-For-Shadow"), String::from("{header:User-Agent}/{shadow}")),
+            ]
+            .into_iter()
+            .collect(),
+            remove_headers: vec![String::from("cookie")],
+            path: vec![Replacement {
+                pattern: String::from("^/api/(\\w+)"),
+                replace: String::from("/v2/$1"),
+            }],
+            marker: None,
+            ..RewriteConfig::default()
+        };
+        let actual = rewrite(
+            config,
+            "GET /api/users?id=1 HTTP/1.1\r\nHost: a\r\nCookie: s=1\r\nUser-Agent: curl\r\nHOST: b\r\n\r\n",
+        );
+        assert_eq!(
+            actual,
+            "GET /v2/users?id=1 HTTP/1.1\r\nHost: shadow.internal\r\nUser-Agent: curl\r\nX-Forwarded-For-Shadow: curl/refactor\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn query_params_removed() {
+        let config = RewriteConfig {
+            remove_query: vec![String::from("_"), String::from("cb")],
+            marker: None,
+            ..RewriteConfig::default()
+        };
+        let actual = rewrite(config, "GET /api?_=1700000000&id=1&cb=x HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert_eq!(actual, "GET /api?id=1 HTTP/1.1\r\nHost: a\r\n\r\n");
+    }
+
+    #[test]
+    fn body_rewrite_updates_content_length() {
+        let config = RewriteConfig {
+            body: vec![Replacement {
+                pattern: String::from("\"env\":\"prod\""),
+                replace: String::from("\"env\":\"staging\""),
+            }],
+            marker: Some(Marker {
+                header: String::from("X-Shadow"),
+                value: String::from("1"),
+            }),
+            ..RewriteConfig::default()
+        };
+        let actual = rewrite(
+            config,
+            "POST /json HTTP/1.1\r\nContent-Length: 14\r\n\r\n{\"env\":\"prod\"}",
+        );
+        assert_eq!(
+            actual,
+            "POST /json HTTP/1.1\r\nContent-Length: 17\r\nX-Shadow: 1\r\n\r\n{\"env\":\"staging\"}"
+        );
+    }
+
+    #[test]
+    fn untouched_lines_are_copied() {
+        // Obs-text, a fold, a line without a colon, a lone CR and mixed line
+        // endings.
+        let request = b"GET /api HTTP/1.1\nHost: a\nX-Name: caf\xe9 \xff\r\nX-Folded: a\r\n b\r\nno colon\r\nX-Cr: a\rb\n\n".to_vec();
+        let rewritten = Rewriter::new(&RewriteConfig::default(), "refactor")
+            .unwrap()
+            .apply(&RawHttpRequest::from(request.clone()));
+
+        let mut expected = request[..request.len() - 1].to_vec();
+        expected.extend_from_slice(b"X-Shadow-Request: refactor\r\n\n");
+        assert_eq!(rewritten.head.to_vec(), expected);
+    }
+
+    #[test]
+    fn body_is_shared() {
+        let request = RawHttpRequest::from(Vec::from("POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\nok"));
+        let rewritten = Rewriter::new(&RewriteConfig::default(), "refactor").unwrap().apply(&request);
+        assert_eq!(rewritten.body, "ok");
+        assert_eq!(rewritten.body.as_ptr(), request.bytes[request.bytes.len() - 2..].as_ptr());
+    }
+
+    #[test]
+    fn bad_templates() {
+        assert!(Template::parse("{shadow").is_err());
+        assert!(Template::parse("{nope}").is_err());
+        assert_eq!(
+            Template::parse("a{shadow}b").unwrap().parts,
+            vec![Part::Literal(String::from("a")), Part::Shadow, Part::Literal(String::from("b"))]
+        );
+    }
+}
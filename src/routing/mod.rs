@@ -0,0 +1,345 @@
+pub mod template;
+
+use crate::config::{Config, RouteConfig, Unmatched};
+use crate::http::{partials::HttpMethod, request::RawHttpRequest, target::RequestTarget};
+use template::Templates;
+
+// Where a request is sent: the main that answers it and the shadows (indices
+// into Config::shadows) it is mirrored to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    // Name of the matched route, used as a label in the metrics.
+    pub route: String,
+    pub main: String,
+    pub shadows: Vec<usize>,
+}
+
+#[derive(Debug)]
+enum PathPattern {
+    Prefix(String),
+    Glob(String),
+    // A route template like /users/{id}.
+    Template(String),
+}
+
+#[derive(Debug)]
+struct Route {
+    method: Option<HttpMethod>,
+    path: Option<PathPattern>,
+    host: Option<String>,
+    target: Target,
+}
+
+// Resolves requests to targets based on the configured routes.
+#[derive(Debug)]
+pub struct Router {
+    routes: Vec<Route>,
+    // Target for requests that match no route, None means reject them.
+    fallback: Option<Target>,
+    templates: Templates,
+}
+
+impl Router {
+    pub fn new(config: &Config) -> Result<Router, String> {
+        // Route paths that are templates are used for normalizing too.
+        let mut templates = config.templates.clone();
+        for route in config.routes.iter() {
+            if let Some(path) = route.path.as_ref().filter(|p| p.contains('{')) {
+                templates.push(path.clone());
+            }
+        }
+        let templates = Templates::new(templates)?;
+
+        if config.routes.is_empty() {
+            return Ok(Router {
+                routes: Vec::new(),
+                fallback: Some(Target {
+                    route: String::from("default"),
+                    main: config.main.clone(),
+                    shadows: (0..config.shadows.len()).collect(),
+                }),
+                templates,
+            });
+        }
+
+        let routes = config
+            .routes
+            .iter()
+            .map(|r| Route::new(r, config))
+            .collect::<Result<Vec<Route>, String>>()?;
+
+        let fallback = match config.unmatched {
+            Unmatched::PassThrough => Some(Target {
+                route: String::from("unmatched"),
+                main: config.main.clone(),
+                shadows: Vec::new(),
+            }),
+            Unmatched::Reject => None,
+        };
+
+        Ok(Router { routes, fallback, templates })
+    }
+
+    // The target for the request, None when the request should be rejected.
+    pub fn resolve(&self, request: &RawHttpRequest) -> Option<&Target> {
+        if self.routes.is_empty() {
+            return self.fallback.as_ref();
+        }
+
+        let (method, target, _) = match request.request_line() {
+            Ok(line) => line,
+            Err(_) => return self.fallback.as_ref(),
+        };
+        // Absolute-form targets are matched on their path too.
+        let target = match RequestTarget::parse(target, method) {
+            Ok(target) => target.origin(),
+            Err(_) => return self.fallback.as_ref(),
+        };
+        let path = target.split('?').next().unwrap_or_default();
+        let host = request.header("host");
+
+        self.routes
+            .iter()
+            .find(|r| r.matches(method, path, host))
+            .map(|r| &r.target)
+            .or(self.fallback.as_ref())
+    }
+
+    pub fn templates(&self) -> &Templates {
+        &self.templates
+    }
+}
+
+impl Route {
+    fn new(route: &RouteConfig, config: &Config) -> Result<Route, String> {
+        let method = match &route.method {
+            Some(m) => Some(
+                HttpMethod::try_from(m.to_ascii_uppercase().as_str())
+                    .map_err(|_| format!("bad method in route: {}", m))?,
+            ),
+            None => None,
+        };
+
+        let path = route.path.as_ref().map(|p| {
+            if p.contains('{') {
+                PathPattern::Template(p.clone())
+            } else if p.contains('*') {
+                PathPattern::Glob(p.clone())
+            } else {
+                PathPattern::Prefix(p.clone())
+            }
+        });
+
+        let shadows = route
+            .shadows
+            .iter()
+            .map(|name| {
+                config
+                    .shadows
+                    .iter()
+                    .position(|s| &s.name == name)
+                    .ok_or_else(|| format!("route refers to unknown shadow {}", name))
+            })
+            .collect::<Result<Vec<usize>, String>>()?;
+
+        Ok(Route {
+            method,
+            path,
+            host: route.host.as_ref().map(|h| h.to_ascii_lowercase()),
+            target: Target {
+                route: route.name(),
+                main: route.main.clone().unwrap_or_else(|| config.main.clone()),
+                shadows,
+            },
+        })
+    }
+
+    fn matches(&self, method: &HttpMethod, path: &str, host: Option<&str>) -> bool {
+        if self.method.as_ref().is_some_and(|m| m != method) {
+            return false;
+        }
+
+        let path_matches = match &self.path {
+            Some(PathPattern::Prefix(prefix)) => path.starts_with(prefix.as_str()),
+            Some(PathPattern::Glob(glob)) => glob_match(glob.as_bytes(), path.as_bytes()),
+            Some(PathPattern::Template(t)) => template::matches(t, path),
+            None => true,
+        };
+        if !path_matches {
+            return false;
+        }
+
+        match (&self.host, host) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(pattern), Some(host)) => {
+                let host = host.to_ascii_lowercase();
+                if pattern.contains(':') {
+                    host == *pattern
+                } else {
+                    host.split(':').next() == Some(pattern.as_str())
+                }
+            }
+        }
+    }
+}
+
+// Matches a path against a glob where '*' matches anything but '/' and '**'
+// matches anything. On a mismatch the last '*' takes one more byte, or when it
+// is at a '/' the last '**' does, so no position is tried twice per wildcard.
+fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // The pattern index after the last '*' and '**' and the path index where
+    // their match ends.
+    let mut star: Option<(usize, usize)> = None;
+    let mut double: Option<(usize, usize)> = None;
+
+    while s < path.len() {
+        if pattern[p..].starts_with(b"**") {
+            p += 2;
+            double = Some((p, s));
+            star = None;
+        } else if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, s));
+        } else if pattern.get(p) == Some(&path[s]) {
+            p += 1;
+            s += 1;
+        } else if let Some((after, end)) = star.filter(|&(_, end)| path[end] != b'/') {
+            p = after;
+            s = end + 1;
+            star = Some((after, s));
+        } else if let Some((after, end)) = double {
+            p = after;
+            s = end + 1;
+            double = Some((after, s));
+            star = None;
+        } else {
+            return false;
+        }
+    }
+
+    // The path is used up, what is left of the pattern has to match nothing.
+    return pattern[p..].iter().all(|&b| b == b'*');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::ShadowConfig;
+
+    fn request(payload: &str) -> RawHttpRequest {
+        RawHttpRequest::from(Vec::from(payload))
+    }
+
+    fn config(unmatched: Unmatched) -> Config {
+        Config {
+            shadows: vec![
+                ShadowConfig { name: String::from("a"), ..ShadowConfig::default() },
+                ShadowConfig { name: String::from("b"), ..ShadowConfig::default() },
+            ],
+            unmatched,
+            routes: vec![
+                RouteConfig {
+                    method: Some(String::from("get")),
+                    path: Some(String::from("/users/*/orders")),
+                    main: Some(String::from("users:1")),
+                    shadows: vec![String::from("b")],
+                    ..RouteConfig::default()
+                },
+                RouteConfig {
+                    path: Some(String::from("/static")),
+                    host: Some(String::from("cdn.example.com")),
+                    main: Some(String::from("cdn:1")),
+                    ..RouteConfig::default()
+                },
+            ],
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob_match(b"/users/*", b"/users/12"));
+        assert!(!glob_match(b"/users/*", b"/users/12/orders"));
+        assert!(glob_match(b"/users/**", b"/users/12/orders"));
+        assert!(glob_match(b"/*.json", b"/data.json"));
+        assert!(!glob_match(b"/*.json", b"/data.xml"));
+        assert!(glob_match(b"/**/*.json", b"/a/b/data.json"));
+        assert!(!glob_match(b"/**/*.json", b"/a/b/data.json/x"));
+        assert!(glob_match(b"/a*/**", b"/ab/c/d"));
+        assert!(!glob_match(b"/a*b", b"/a/b"));
+    }
+
+    #[test]
+    fn pathological_glob() {
+        // Took exponential time with backtracking on every '*'.
+        let path = format!("/{}", "a".repeat(64));
+        let pattern = format!("/{}b", "**a".repeat(16));
+        assert!(!glob_match(pattern.as_bytes(), path.as_bytes()));
+        let pattern = format!("/{}b", "*a".repeat(16));
+        assert!(!glob_match(pattern.as_bytes(), path.as_bytes()));
+        assert!(glob_match(pattern.as_bytes(), format!("{}b", path).as_bytes()));
+    }
+
+    #[test]
+    fn without_routes_everything_is_mirrored() {
+        let router = Router::new(&Config::default()).expect("valid routes");
+        let target = router.resolve(&request("GET / HTTP/1.1\r\n\r\n")).expect("a target");
+        assert_eq!(target.main, "127.0.0.1:4001");
+        assert_eq!(target.shadows, vec![0]);
+    }
+
+    #[test]
+    fn method_and_glob() {
+        let router = Router::new(&config(Unmatched::Reject)).expect("valid routes");
+
+        let target = router
+            .resolve(&request("GET /users/7/orders?page=2 HTTP/1.1\r\n\r\n"))
+            .expect("a target");
+        assert_eq!(target.main, "users:1");
+        assert_eq!(target.shadows, vec![1]);
+
+        assert!(router.resolve(&request("POST /users/7/orders HTTP/1.1\r\n\r\n")).is_none());
+    }
+
+    #[test]
+    fn host_and_prefix() {
+        let router = Router::new(&config(Unmatched::PassThrough)).expect("valid routes");
+
+        let target = router
+            .resolve(&request("GET /static/app.js HTTP/1.1\r\nHost: CDN.example.com:8080\r\n\r\n"))
+            .expect("a target");
+        assert_eq!(target.main, "cdn:1");
+        assert!(target.shadows.is_empty());
+
+        let target = router
+            .resolve(&request("GET /static/app.js HTTP/1.1\r\nHost: other\r\n\r\n"))
+            .expect("a target");
+        assert_eq!(target.main, "127.0.0.1:4001");
+        assert!(target.shadows.is_empty());
+    }
+
+    #[test]
+    fn route_templates() {
+        let config = Config {
+            templates: vec![String::from("/repos/{owner}/{repo}")],
+            routes: vec![RouteConfig {
+                name: Some(String::from("user")),
+                path: Some(String::from("/users/{user}")),
+                ..RouteConfig::default()
+            }],
+            ..Config::default()
+        };
+        let router = Router::new(&config).expect("valid routes");
+
+        let target = router.resolve(&request("GET /users/jelte HTTP/1.1\r\n\r\n")).expect("a target");
+        assert_eq!(target.route, "user");
+        let target = router.resolve(&request("GET /users/jelte/x HTTP/1.1\r\n\r\n")).expect("a target");
+        assert_eq!(target.route, "unmatched");
+
+        assert_eq!(router.templates().normalize("/users/jelte?x=1"), "/users/{user}");
+        assert_eq!(router.templates().normalize("/repos/a/b"), "/repos/{owner}/{repo}");
+        assert_eq!(router.templates().normalize("/orders/77"), "/orders/{id}");
+    }
+}
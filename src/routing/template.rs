@@ -0,0 +1,120 @@
+// Route templates group request paths that only differ in their parameters,
+// e.g. /users/123 and /users/124 both become /users/{id}. Paths are matched
+// against the configured templates first. Otherwise segments that look like
+// parameters are replaced:
+//   only digits                          {id}
+//   a UUID with dashes                   {uuid}
+//   16 or more hex digits, with a digit  {hash}
+#[derive(Debug, Default, Clone)]
+pub struct Templates {
+    templates: Vec<String>,
+}
+
+impl Templates {
+    pub fn new(templates: Vec<String>) -> Result<Templates, String> {
+        for template in templates.iter() {
+            if !template.starts_with('/') {
+                return Err(format!("route template should start with '/': {}", template));
+            }
+        }
+        return Ok(Templates { templates });
+    }
+
+    // The template for a request target, the query string is left out.
+    pub fn normalize(&self, target: &str) -> String {
+        let path = target.split('?').next().unwrap_or_default();
+
+        if let Some(template) = self.templates.iter().find(|t| matches(t, path)) {
+            return template.clone();
+        }
+
+        return path
+            .split('/')
+            .map(|segment| match infer(segment) {
+                Some(parameter) => parameter,
+                None => segment,
+            })
+            .collect::<Vec<&str>>()
+            .join("/");
+    }
+}
+
+// Whether the path fits the template: the same number of segments, where
+// '{name}' segments match any non-empty segment and the others must be equal.
+pub fn matches(template: &str, path: &str) -> bool {
+    let mut template = template.split('/');
+    let mut path = path.split('/');
+
+    loop {
+        match (template.next(), path.next()) {
+            (None, None) => return true,
+            (Some(t), Some(p)) if is_parameter(t) => {
+                if p.is_empty() {
+                    return false;
+                }
+            }
+            (Some(t), Some(p)) if t == p => {}
+            _ => return false,
+        }
+    }
+}
+
+fn is_parameter(segment: &str) -> bool {
+    segment.len() > 2 && segment.starts_with('{') && segment.ends_with('}')
+}
+
+fn infer(segment: &str) -> Option<&'static str> {
+    if segment.is_empty() {
+        return None;
+    }
+    if segment.bytes().all(|b| b.is_ascii_digit()) {
+        return Some("{id}");
+    }
+    if segment.len() == 36 && uuid::Uuid::try_parse(segment).is_ok() {
+        return Some("{uuid}");
+    }
+    if segment.len() >= 16
+        && segment.bytes().all(|b| b.is_ascii_hexdigit())
+        && segment.bytes().any(|b| b.is_ascii_digit())
+    {
+        return Some("{hash}");
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn inference() {
+        let templates = Templates::default();
+        assert_eq!(templates.normalize("/users/123?full=1"), "/users/{id}");
+        assert_eq!(
+            templates.normalize("/files/0f8fad5b-d9cb-469f-a165-70867728950e/raw"),
+            "/files/{uuid}/raw"
+        );
+        assert_eq!(templates.normalize("/blobs/9b74c9897bac770ffc029102a200c5de"), "/blobs/{hash}");
+        assert_eq!(templates.normalize("/feedbackfeedback"), "/feedbackfeedback");
+        assert_eq!(templates.normalize("/v2/users/"), "/v2/users/");
+        assert_eq!(templates.normalize("/"), "/");
+    }
+
+    #[test]
+    fn configured() {
+        let templates = Templates::new(vec![String::from("/users/{name}/repos/{repo}")]).unwrap();
+        assert_eq!(templates.normalize("/users/jelte/repos/shadowapi"), "/users/{name}/repos/{repo}");
+        assert_eq!(templates.normalize("/users/jelte/repos"), "/users/jelte/repos");
+        assert_eq!(templates.normalize("/users/42/repos/"), "/users/{id}/repos/");
+
+        assert!(Templates::new(vec![String::from("users/{id}")]).is_err());
+    }
+
+    #[test]
+    fn matching() {
+        assert!(matches("/users/{id}", "/users/1"));
+        assert!(!matches("/users/{id}", "/users/"));
+        assert!(!matches("/users/{id}", "/users/1/orders"));
+        assert!(matches("/users/{}", "/users/{}"));
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use tokio::sync::mpsc::Receiver;
+
+use crate::compare::ComparisonRecord;
+
+// Comparison results are stored as JSON lines, one record per line, so the
+// file can be appended to by the proxy while other tools read it.
+//
+// Every mismatch is stored, but the raw exchange is only kept for the first
+// few mismatches of each signature since most of them are the same failure.
+pub struct ResultStore {
+    file: File,
+    samples_per_cluster: usize,
+    // Stored exchanges per signature.
+    samples: HashMap<String, usize>,
+}
+
+// What opening a store needs to know about a stored record.
+#[derive(Deserialize)]
+struct Sample {
+    #[serde(default)]
+    signature: Option<String>,
+    #[serde(default)]
+    exchange: Option<IgnoredAny>,
+}
+
+impl ResultStore {
+    pub fn open<T>(path: T, samples_per_cluster: usize) -> Result<ResultStore, std::io::Error>
+    where
+        T: AsRef<Path>,
+    {
+        let file = OpenOptions::new().create(true).append(true).open(path.as_ref())?;
+
+        // Samples already in the file count towards the limit. The file is
+        // read a line at a time and only the signature is decoded, the
+        // exchanges are skipped over.
+        let mut samples: HashMap<String, usize> = HashMap::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            if let Ok(Sample { signature: Some(signature), exchange: Some(_) }) = serde_json::from_str(&line?) {
+                *samples.entry(signature).or_default() += 1;
+            }
+        }
+
+        Ok(ResultStore { file, samples_per_cluster, samples })
+    }
+
+    pub fn append(&mut self, record: &ComparisonRecord) -> Result<(), std::io::Error> {
+        if let (Some(signature), Some(_)) = (&record.signature, &record.exchange) {
+            let stored = self.samples.entry(signature.clone()).or_default();
+            if *stored >= self.samples_per_cluster {
+                let record = ComparisonRecord {
+                    exchange: None,
+                    ..record.clone()
+                };
+                return self.write(&record);
+            }
+            *stored += 1;
+        }
+        return self.write(record);
+    }
+
+    fn write(&mut self, record: &ComparisonRecord) -> Result<(), std::io::Error> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+
+    // Writes every record received on the channel until all senders are
+    // dropped, then makes sure the records are on disk.
+    pub async fn run(mut self, mut rx: Receiver<ComparisonRecord>) {
+        while let Some(record) = rx.recv().await {
+            if let Err(e) = self.append(&record) {
+                tracing::error!("error writing to result store: {}", e);
+            }
+        }
+        if let Err(e) = self.file.sync_all() {
+            tracing::error!("error syncing result store: {}", e);
+        }
+    }
+}
+
+// Reads all records from a result store. Lines that can not be parsed are
+// skipped so a partially written last line does not make the file unusable.
+pub fn read_records<T>(path: T) -> Result<Vec<ComparisonRecord>, std::io::Error>
+where
+    T: AsRef<Path>,
+{
+    let reader = BufReader::new(File::open(path)?);
+    let mut records: Vec<ComparisonRecord> = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if let Ok(record) = serde_json::from_str::<ComparisonRecord>(&line) {
+            records.push(record);
+        }
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compare::{self, Exchange};
+    use crate::config::CompareConfig;
+    use crate::http::{request::RawHttpRequest, response::RawHttpResponse};
+    use crate::routing::template::Templates;
+
+    fn mismatch() -> ComparisonRecord {
+        let request = RawHttpRequest::from(Vec::from("GET /a HTTP/1.1\r\n\r\n"));
+        let main = RawHttpResponse::from(Vec::from("HTTP/1.1 200 OK\r\n\r\n"));
+        let shadow = RawHttpResponse::from(Vec::from("HTTP/1.1 500 Internal Server Error\r\n\r\n"));
+        compare::evaluate("s", "default", &Templates::default(), request, main, Ok(shadow), &CompareConfig::default())
+    }
+
+    #[test]
+    fn samples_per_cluster() {
+        let path = std::env::temp_dir().join(format!("shadowapi-store-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = ResultStore::open(&path, 2).unwrap();
+        for _ in 0..3 {
+            store.append(&mismatch()).unwrap();
+        }
+        // Reopening keeps counting the samples that are already stored.
+        let mut store = ResultStore::open(&path, 2).unwrap();
+        store.append(&mismatch()).unwrap();
+
+        let records = read_records(&path).unwrap();
+        let kept: Vec<Option<&Exchange>> = records.iter().map(|r| r.exchange.as_ref()).collect();
+        assert_eq!(records.len(), 4);
+        assert!(kept[0].is_some() && kept[1].is_some());
+        assert!(kept[2].is_none() && kept[3].is_none());
+        assert!(records.iter().all(|r| r.signature == records[0].signature));
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
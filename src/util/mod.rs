@@ -1,75 +1,244 @@
 pub mod log {
-    use std::fmt::Display;
-    use std::path::PathBuf;
-    use std::fs::File;
-    use std::io::Write;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{self, Write};
+    use std::path::{Path, PathBuf};
 
-    pub fn timed_msg<T>(msg: T, when: chrono::DateTime<chrono::Utc>)
-    where T: Display
+    use tracing::Subscriber;
+    use tracing_appender::non_blocking::WorkerGuard;
+    use tracing_subscriber::filter::{filter_fn, EnvFilter};
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::registry::LookupSpan;
+    use tracing_subscriber::util::SubscriberInitExt;
+    use tracing_subscriber::Layer;
+
+    use crate::config::{LogFormat, LoggingConfig, RotationConfig, RotationPeriod};
+
+    // Target of the events that end up in shadow.log, e.g.
+    // `tracing::warn!(target: log::SHADOW, ...)`.
+    pub const SHADOW: &str = "shadow";
+
+    // Keeps the background log writers alive. Events that are still queued
+    // are written when this is dropped, so hold on to it until exit.
+    pub struct Guards(Vec<WorkerGuard>);
+
+    // Installs the global subscriber: shadow events go to shadow.log, all
+    // other events to main.log, and everything to stdout when enabled.
+    pub fn init(config: &LoggingConfig) -> io::Result<Guards> {
+        let filter = EnvFilter::try_new(&config.level)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        fs::create_dir_all(&config.directory)?;
+
+        let main_file = RotatingFile::open(config.directory.join("main.log"), config.rotation.clone())?;
+        let shadow_file = RotatingFile::open(config.directory.join("shadow.log"), config.rotation.clone())?;
+        let (main_writer, main_guard) = tracing_appender::non_blocking(main_file);
+        let (shadow_writer, shadow_guard) = tracing_appender::non_blocking(shadow_file);
+
+        // Spans pass both filters so events in either file carry the
+        // exchange ID.
+        let mut layers = vec![
+            layer(config.format, main_writer, false)
+                .with_filter(filter_fn(|meta| meta.is_span() || meta.target() != SHADOW))
+                .boxed(),
+            layer(config.format, shadow_writer, false)
+                .with_filter(filter_fn(|meta| meta.is_span() || meta.target() == SHADOW))
+                .boxed(),
+        ];
+        if config.stdout {
+            layers.push(layer(config.format, io::stdout, true));
+        }
+
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(layers)
+            .try_init()
+            .map_err(io::Error::other)?;
+
+        return Ok(Guards(vec![main_guard, shadow_guard]));
+    }
+
+    fn layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
     {
-        println!("{}: {}", when, msg);
+        let fmt = tracing_subscriber::fmt::layer().with_writer(writer);
+        return match format {
+            LogFormat::Plain => fmt.with_ansi(ansi).boxed(),
+            LogFormat::Json => fmt.json().with_current_span(true).boxed(),
+        };
     }
 
-    pub struct LoggingState {
-        shadow_file: File,
-        main_file: File,
+    // A log file in append mode that is rotated by size and/or time.
+    pub struct RotatingFile {
+        path: PathBuf,
+        file: File,
+        written: u64,
+        period: Option<i64>,
+        rotation: RotationConfig,
     }
 
-    impl LoggingState {
-        pub fn new<T>(dir: T) -> Option<LoggingState>
+    impl RotatingFile {
+        pub fn open<T>(path: T, rotation: RotationConfig) -> io::Result<RotatingFile>
         where T: Into<PathBuf>
         {
-            let directory: PathBuf = dir.into();
+            let path: PathBuf = path.into();
+            let file = OpenOptions::new().create(true).append(true).open(&path)?;
+            let meta = file.metadata()?;
 
-            let mut shadow_file: PathBuf = directory.clone();
-            shadow_file.push("shadow.log");
-
-            let mut log_file: PathBuf = directory.clone();
-            log_file.push("main.log");
-
-            // TODO: files should not be truncated when created
-            let shadow_file = match File::create(shadow_file) {
-                Ok(f) => f,
-                Err(_) => return None,
+            // The period of an existing file is taken from when it was last
+            // written, so a restart on the next day still rotates it.
+            let modified: chrono::DateTime<chrono::Utc> = match meta.modified() {
+                Ok(time) => time.into(),
+                Err(_) => chrono::Utc::now(),
             };
+            let period = rotation.every.map(|every| period_of(every, modified));
 
-            let log_file = match File::create(log_file) {
-                Ok(f) => f,
-                Err(_) => return None,
-            };
+            return Ok(RotatingFile {
+                path,
+                file,
+                written: meta.len(),
+                period,
+                rotation,
+            });
+        }
 
-            Some(LoggingState {
-                shadow_file,
-                main_file: log_file,
-            })
+        fn due(&self, incoming: usize) -> bool {
+            if let Some(max) = self.rotation.max_bytes {
+                if self.written > 0 && self.written + incoming as u64 > max {
+                    return true;
+                }
+            }
+            if let (Some(every), Some(period)) = (self.rotation.every, self.period) {
+                if period_of(every, chrono::Utc::now()) != period {
+                    return true;
+                }
+            }
+            return false;
         }
-    }
 
-    impl LoggingState {
+        // Shifts main.log.1 to main.log.2 and so on, dropping the oldest, and
+        // starts a new empty file.
+        fn rotate(&mut self) -> io::Result<()> {
+            self.file.flush()?;
+            let keep = self.rotation.keep;
 
-        pub fn shadow_info<T>(&mut self, msg: T)
-        where T: Display
-        {
-            let _ = self.shadow_file.write_all(format!("{}:[INFO] {}\n", chrono::Utc::now(), msg).as_bytes());
+            if keep == 0 {
+                fs::remove_file(&self.path)?;
+            } else {
+                let _ = fs::remove_file(numbered(&self.path, keep));
+                for n in (1..keep).rev() {
+                    let from = numbered(&self.path, n);
+                    if from.exists() {
+                        fs::rename(from, numbered(&self.path, n + 1))?;
+                    }
+                }
+                fs::rename(&self.path, numbered(&self.path, 1))?;
+            }
+
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.written = 0;
+            self.period = self.rotation.every.map(|every| period_of(every, chrono::Utc::now()));
+            return Ok(());
         }
+    }
 
-        pub fn main_info<T>(&mut self, msg: T)
-        where T: Display
-        {
-            let _ = self.main_file.write_all(format!("{}:[INFO] {}\n", chrono::Utc::now(), msg).as_bytes());
+    impl Write for RotatingFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.due(buf.len()) {
+                self.rotate()?;
+            }
+            let n = self.file.write(buf)?;
+            self.written += n as u64;
+            return Ok(n);
         }
 
-        pub fn main<T>(&mut self, msg: T)
-        where T: Display
-        {
-            self.main_info(msg);
+        fn flush(&mut self) -> io::Result<()> {
+            return self.file.flush();
         }
+    }
 
-        pub fn shadow<T>(&mut self, msg: T)
-        where T: Display
-        {
-            self.shadow_info(msg);
+    fn numbered(path: &Path, n: usize) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        return PathBuf::from(name);
+    }
+
+    fn period_of(every: RotationPeriod, when: chrono::DateTime<chrono::Utc>) -> i64 {
+        let seconds = match every {
+            RotationPeriod::Hourly => 3_600,
+            RotationPeriod::Daily => 86_400,
+        };
+        return when.timestamp().div_euclid(seconds);
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn rotates_by_size() {
+            let dir = std::env::temp_dir().join(format!("shadowapi-log-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("main.log");
+
+            let rotation = RotationConfig {
+                max_bytes: Some(10),
+                every: None,
+                keep: 2,
+            };
+            let mut file = RotatingFile::open(&path, rotation).unwrap();
+            for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+                file.write_all(line.as_bytes()).unwrap();
+            }
+
+            assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+            assert_eq!(fs::read_to_string(numbered(&path, 1)).unwrap(), "third\n");
+            assert_eq!(fs::read_to_string(numbered(&path, 2)).unwrap(), "second\n");
+            assert!(!numbered(&path, 3).exists());
+
+            let _ = fs::remove_dir_all(&dir);
         }
+    }
+}
+
+// Serde helpers to store raw message bytes as base64 strings in JSON.
+pub mod base64 {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
 
+    pub fn encode(bytes: &[u8]) -> String {
+        STANDARD.encode(bytes)
+    }
+
+    pub fn decode(text: &str) -> Option<Vec<u8>> {
+        STANDARD.decode(text).ok()
+    }
+
+    pub fn serialize<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer
+    {
+        serializer.serialize_str(&encode(bytes))
+    }
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where D: Deserializer<'de>, T: From<Vec<u8>>
+    {
+        let text = String::deserialize(deserializer)?;
+        decode(&text).map(T::from).ok_or_else(|| serde::de::Error::custom("invalid base64"))
+    }
+}
+
+pub mod hash {
+    // 64 bit FNV-1a, stable across runs and platforms unlike the std hasher.
+    pub fn fnv1a(bytes: &[u8]) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for &byte in bytes {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        hash
     }
 }